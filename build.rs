@@ -0,0 +1,15 @@
+//! Sets the `timing_off` cfg when timers are compiled out; see the
+//! `toggle` module.
+
+use std::env;
+
+fn main() {
+    // the single-colon form is ignored by cargo versions without check-cfg
+    println!("cargo:rustc-check-cfg=cfg(timing_off)");
+    let off = env::var_os("CARGO_FEATURE_TIMING_OFF").is_some()
+        || (env::var_os("CARGO_FEATURE_RELEASE_TIMING_OFF").is_some()
+            && env::var_os("CARGO_CFG_DEBUG_ASSERTIONS").is_none());
+    if off {
+        println!("cargo:rustc-cfg=timing_off");
+    }
+}
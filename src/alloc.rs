@@ -0,0 +1,287 @@
+//! Counting allocations, so that timers can report the memory use of the
+//! code they measure.
+//!
+//! To use this, install a `CountingAllocator` as the global allocator:
+//!
+//! ```
+//! use test_helpers::alloc::CountingAllocator;
+//!
+//! #[global_allocator]
+//! static ALLOC: CountingAllocator = CountingAllocator::new();
+//! # fn main() {}
+//! ```
+//!
+//! Once it is installed, every `BlockTimer` reports the allocations made
+//! on its thread while it was running (including while it was paused). The
+//! allocations made by the timers themselves, including nested timers, are
+//! not counted.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static COUNTS: Cell<Counts> = const { Cell::new(Counts::ZERO) };
+    /// Greater than zero while the thread is running `untracked` code.
+    static UNTRACKED: Cell<u32> = const { Cell::new(0) };
+}
+
+/// A global allocator that counts the allocations made on each thread,
+/// passing the allocations themselves on to another allocator.
+#[derive(Debug, Default)]
+pub struct CountingAllocator<A = System> {
+    inner: A,
+}
+
+/// The allocations made by a thread while a timer was running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AllocStats {
+    /// The number of allocations, including reallocations.
+    pub count: u64,
+    pub bytes_allocated: u64,
+    pub bytes_freed: u64,
+    /// The largest increase in the number of live bytes, relative to when
+    /// the timer started.
+    pub peak: u64,
+}
+
+/// A running total of a thread's allocations.
+#[derive(Debug, Clone, Copy)]
+struct Counts {
+    count: u64,
+    allocated: u64,
+    freed: u64,
+    /// The most bytes that have been live (allocated minus freed) at once
+    /// since the last `AllocScope` started.
+    peak: u64,
+}
+
+/// Measures the allocations on the current thread between its creation
+/// and a call to `finish`.
+#[cfg_attr(timing_off, allow(dead_code))]
+pub(crate) struct AllocScope {
+    start: Counts,
+    outer_peak: u64,
+}
+
+/// Returns `true` if a `CountingAllocator` is the global allocator, and has
+/// allocated anything.
+pub fn is_installed() -> bool {
+    INSTALLED.load(Ordering::Relaxed)
+}
+
+impl CountingAllocator<System> {
+    pub const fn new() -> Self {
+        CountingAllocator { inner: System }
+    }
+}
+
+impl<A> CountingAllocator<A> {
+    /// Counts the allocations made through `inner`.
+    pub const fn wrap(inner: A) -> Self {
+        CountingAllocator { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            record(layout.size(), 0);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record(layout.size(), 0);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        record(0, layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            record(new_size, layout.size());
+        }
+        new_ptr
+    }
+}
+
+fn record(allocated: usize, freed: usize) {
+    if !INSTALLED.load(Ordering::Relaxed) {
+        INSTALLED.store(true, Ordering::Relaxed);
+    }
+    if UNTRACKED.try_with(|n| n.get() > 0).unwrap_or(true) {
+        return;
+    }
+    // this fails only during thread teardown, when we can't count anyway
+    let _ = COUNTS.try_with(|counts| {
+        let mut c = counts.get();
+        if allocated > 0 {
+            c.count += 1;
+        }
+        c.allocated += allocated as u64;
+        c.freed += freed as u64;
+        c.peak = c.peak.max(c.live());
+        counts.set(c);
+    });
+}
+
+/// Runs `f` without counting its allocations on this thread. This is used
+/// for the timers' own bookkeeping, so that it doesn't show up in the
+/// counts of the timers around it.
+pub(crate) fn untracked<T, F: FnOnce() -> T>(f: F) -> T {
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            let _ = UNTRACKED.try_with(|n| n.set(n.get() - 1));
+        }
+    }
+
+    let _ = UNTRACKED.try_with(|n| n.set(n.get() + 1));
+    let _guard = Guard;
+    f()
+}
+
+impl Counts {
+    const ZERO: Counts = Counts { count: 0, allocated: 0, freed: 0, peak: 0 };
+
+    fn live(&self) -> u64 {
+        self.allocated.saturating_sub(self.freed)
+    }
+
+    #[cfg_attr(timing_off, allow(dead_code))]
+    fn current() -> Counts {
+        COUNTS.try_with(Cell::get).unwrap_or(Counts::ZERO)
+    }
+}
+
+#[cfg_attr(timing_off, allow(dead_code))]
+impl AllocScope {
+    /// Starts measuring, if a `CountingAllocator` is installed.
+    pub(crate) fn start() -> Option<AllocScope> {
+        if !is_installed() {
+            return None;
+        }
+        let start = Counts::current();
+        // reset the peak, so that we see the peak within this scope; the
+        // outer peak is restored when we finish.
+        let _ = COUNTS.try_with(|counts| {
+            counts.set(Counts { peak: start.live(), ..start })
+        });
+        Some(AllocScope { start, outer_peak: start.peak })
+    }
+
+    pub(crate) fn finish(&self) -> AllocStats {
+        let end = Counts::current();
+        let _ = COUNTS.try_with(|counts| {
+            counts.set(Counts { peak: end.peak.max(self.outer_peak), ..end })
+        });
+        AllocStats {
+            count: end.count - self.start.count,
+            bytes_allocated: end.allocated - self.start.allocated,
+            bytes_freed: end.freed - self.start.freed,
+            peak: end.peak.saturating_sub(self.start.live()),
+        }
+    }
+}
+
+impl fmt::Display for AllocStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = if self.count == 1 { "" } else { "s" };
+        write!(f, "{} alloc{}, {} allocated, {} freed, peak +{}", self.count, plural,
+               Bytes(self.bytes_allocated), Bytes(self.bytes_freed), Bytes(self.peak))
+    }
+}
+
+/// Formats a number of bytes with a binary unit.
+struct Bytes(u64);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return write!(f, "{}B", self.0);
+        }
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1}{}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(not(timing_off))]
+    use std::sync::Arc;
+    #[cfg(not(timing_off))]
+    use sink::Collector;
+    #[cfg(not(timing_off))]
+    use BlockTimer;
+
+    #[global_allocator]
+    static ALLOC: CountingAllocator = CountingAllocator::new();
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn counts_allocations() {
+        let collector = Arc::new(Collector::new());
+        let timer = BlockTimer::with_sink("alloc", collector.clone());
+        {
+            let mut a = Vec::<u8>::with_capacity(1000);
+            a.push(1);
+            let b = Vec::<u8>::with_capacity(500);
+            drop((a, b));
+        }
+        let kept = Vec::<u8>::with_capacity(100);
+        drop(timer);
+        drop(kept);
+        let stats = collector.take()[0].allocations.unwrap();
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.bytes_allocated, 1600);
+        assert_eq!(stats.bytes_freed, 1500);
+        assert_eq!(stats.peak, 1500);
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn nested_timers_not_counted() {
+        let collector = Arc::new(Collector::new());
+        let outer = BlockTimer::with_sink("outer", collector.clone());
+        for i in 0..20 {
+            let mut inner = BlockTimer::new(format!("inner {}", i)).with_field("i", i);
+            inner.lap("lap");
+            drop(Vec::<u8>::with_capacity(10));
+        }
+        drop(outer);
+        let report = &collector.take()[0];
+        assert_eq!(report.children.len(), 20);
+        // each iteration allocates only a label and a vector
+        let stats = report.allocations.unwrap();
+        assert_eq!(stats.count, 40);
+        assert_eq!(stats.bytes_freed, 20 * 10);
+    }
+
+    #[test]
+    fn display() {
+        let stats = AllocStats { count: 3, bytes_allocated: 1536, bytes_freed: 12,
+                                 peak: 3 * 1024 * 1024 };
+        assert_eq!(stats.to_string(),
+                   "3 allocs, 1.5KiB allocated, 12B freed, peak +3.0MiB");
+    }
+}
@@ -0,0 +1,304 @@
+//! Saving aggregated timings to a file, and comparing later runs against
+//! them to catch regressions.
+//!
+//! ```no_run
+//! use test_helpers::{baseline, registry};
+//!
+//! registry::enable();
+//! // ... run the code being timed ...
+//! let path = baseline::default_path();
+//! // fails if any label got more than 20% slower than in the baseline
+//! baseline::check(registry::global(), &path, Some(20.0)).unwrap();
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{self, Write as FmtWrite};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use exit;
+use json::{self, Json};
+use registry::Registry;
+use PrettyDuration;
+
+/// Changes smaller than this percentage are reported as unchanged.
+const NOISE_PERCENT: f64 = 5.0;
+
+/// A snapshot of aggregated timings, keyed by label.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Baseline {
+    pub entries: BTreeMap<String, BaselineEntry>,
+}
+
+/// The timings recorded for one label in a `Baseline`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BaselineEntry {
+    pub count: u64,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+/// The differences between a baseline and a later run.
+///
+/// The `Display` impl shows one line per label, such as
+/// `parse: 12.1ms → 15.3ms (+26%, slower)`.
+#[derive(Debug, Clone, Default)]
+pub struct Comparison {
+    pub deltas: Vec<Delta>,
+}
+
+/// The change in the mean time of one label.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delta {
+    pub label: String,
+    /// The mean time in the baseline, if the label was present.
+    pub before: Option<Duration>,
+    /// The mean time in the later run, if the label was present.
+    pub after: Option<Duration>,
+}
+
+/// The default location of the baseline file,
+/// `target/test-helpers/baseline.json`, respecting `CARGO_TARGET_DIR`.
+pub fn default_path() -> PathBuf {
+    let target = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("target"));
+    target.join("test-helpers").join("baseline.json")
+}
+
+/// Compares the timings in `registry` against the baseline at `path`,
+/// printing the comparison to stderr. If there is no baseline yet, the
+/// current timings are saved as the baseline instead.
+///
+/// If `max_regression` is given, panics if the mean time of any label has
+/// increased by more than that percentage.
+pub fn check<P: AsRef<Path>>(registry: &Registry, path: P,
+                             max_regression: Option<f64>) -> io::Result<Comparison> {
+    let path = path.as_ref();
+    let current = Baseline::from_registry(registry);
+    if !path.exists() {
+        current.save(path)?;
+        eprintln!("saved timing baseline to {}", path.display());
+        return Ok(Comparison::default());
+    }
+    let comparison = Baseline::load(path)?.compare(&current);
+    eprintln!("{}", comparison);
+    if let Some(max_regression) = max_regression {
+        comparison.assert_no_regressions(max_regression);
+    }
+    Ok(comparison)
+}
+
+impl Baseline {
+    /// Takes a snapshot of the current statistics in `registry`.
+    pub fn from_registry(registry: &Registry) -> Self {
+        let entries = registry.summary().entries.into_iter()
+            .map(|(label, stats)| {
+                let entry = BaselineEntry {
+                    count: stats.count,
+                    mean: stats.mean(),
+                    p50: stats.histogram.p50(),
+                    min: stats.min,
+                    max: stats.max,
+                };
+                (label, entry)
+            })
+            .collect();
+        Baseline { entries }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Baseline::from_json(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the baseline to `path`, creating any missing parent
+    /// directories.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        exit::write_file(path, |writer| writer.write_all(self.to_json().as_bytes()))
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{");
+        for (i, (label, entry)) in self.entries.iter().enumerate() {
+            out.push_str(if i == 0 { "\n  " } else { ",\n  " });
+            json::write_str(&mut out, label).unwrap();
+            write!(out, ": {{\"count\": {}, \"mean_ns\": {}, \"p50_ns\": {}, \
+                         \"min_ns\": {}, \"max_ns\": {}}}",
+                   entry.count, entry.mean.as_nanos(), entry.p50.as_nanos(),
+                   entry.min.as_nanos(), entry.max.as_nanos()).unwrap();
+        }
+        out.push_str("\n}\n");
+        out
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let members = match json::parse(text)? {
+            Json::Object(members) => members,
+            _ => return Err("expected an object".into()),
+        };
+        let mut entries = BTreeMap::new();
+        for (label, value) in members {
+            let field = |key: &str| {
+                value.get(key)
+                    .and_then(Json::as_f64)
+                    .map(|n| n as u64)
+                    .ok_or_else(|| format!("missing or invalid '{}' for '{}'", key, label))
+            };
+            let entry = BaselineEntry {
+                count: field("count")?,
+                mean: Duration::from_nanos(field("mean_ns")?),
+                p50: Duration::from_nanos(field("p50_ns")?),
+                min: Duration::from_nanos(field("min_ns")?),
+                max: Duration::from_nanos(field("max_ns")?),
+            };
+            entries.insert(label, entry);
+        }
+        Ok(Baseline { entries })
+    }
+
+    /// Compares the mean time of each label in this baseline with that in
+    /// `current`.
+    pub fn compare(&self, current: &Baseline) -> Comparison {
+        let mut labels = self.entries.keys().collect::<Vec<_>>();
+        labels.extend(current.entries.keys().filter(|l| !self.entries.contains_key(*l)));
+        let deltas = labels.into_iter()
+            .map(|label| Delta {
+                label: label.clone(),
+                before: self.entries.get(label).map(|e| e.mean),
+                after: current.entries.get(label).map(|e| e.mean),
+            })
+            .collect();
+        Comparison { deltas }
+    }
+}
+
+impl Comparison {
+    /// Returns the labels whose mean time increased by more than
+    /// `max_percent`.
+    pub fn regressions(&self, max_percent: f64) -> Vec<&Delta> {
+        self.deltas.iter()
+            .filter(|d| d.change().map(|c| c > max_percent).unwrap_or(false))
+            .collect()
+    }
+
+    /// Panics if any label's mean time increased by more than
+    /// `max_percent`, listing the regressions.
+    pub fn assert_no_regressions(&self, max_percent: f64) {
+        let regressions = self.regressions(max_percent);
+        if !regressions.is_empty() {
+            let list = regressions.iter()
+                .map(|d| format!("  {}", d))
+                .collect::<Vec<_>>()
+                .join("\n");
+            panic!("timings regressed by more than {}%:\n{}", max_percent, list);
+        }
+    }
+}
+
+impl Delta {
+    /// The change in mean time as a percentage of the baseline, if the
+    /// label is present in both runs.
+    pub fn change(&self) -> Option<f64> {
+        match (self.before, self.after) {
+            (Some(before), Some(after)) if before > Duration::default() => {
+                Some((after.as_secs_f64() / before.as_secs_f64() - 1.0) * 100.0)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Delta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pretty = |d: Option<Duration>| match d {
+            Some(d) => PrettyDuration::new(d).to_string(),
+            None => "-".to_string(),
+        };
+        write!(f, "{}: {} → {}", self.label, pretty(self.before), pretty(self.after))?;
+        match (self.change(), self.before, self.after) {
+            (Some(change), _, _) => {
+                let verdict = if change >= NOISE_PERCENT {
+                    "slower"
+                } else if change <= -NOISE_PERCENT {
+                    "faster"
+                } else {
+                    "unchanged"
+                };
+                write!(f, " ({:+.0}%, {})", change, verdict)
+            }
+            (_, None, Some(_)) => f.write_str(" (new)"),
+            (_, Some(_), None) => f.write_str(" (missing)"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, delta) in self.deltas.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", delta)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(timings: &[(&str, u64)]) -> Registry {
+        let registry = Registry::new();
+        for &(label, micros) in timings {
+            registry.record_duration(label, Duration::from_micros(micros));
+        }
+        registry
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let baseline = Baseline::from_registry(&registry(&[("parse", 12100), ("a\"b", 5)]));
+        let json = baseline.to_json();
+        assert_eq!(Baseline::from_json(&json), Ok(baseline));
+        assert!(Baseline::from_json("{\"parse\": {}}").is_err());
+    }
+
+    #[test]
+    fn compare() {
+        let before = Baseline::from_registry(&registry(&[
+            ("parse", 12100), ("render", 1000), ("layout", 500), ("old", 1),
+        ]));
+        let after = Baseline::from_registry(&registry(&[
+            ("parse", 15300), ("render", 1020), ("layout", 250), ("new", 3000),
+        ]));
+        let comparison = before.compare(&after);
+        let expected = "\
+layout: 500us → 250us (-50%, faster)
+old: 1us → - (missing)
+parse: 12.1ms → 15.3ms (+26%, slower)
+render: 1.0ms → 1.0ms (+2%, unchanged)
+new: - → 3.0ms (new)";
+        assert_eq!(comparison.to_string(), expected);
+        let regressions = comparison.regressions(10.0);
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].label, "parse");
+        comparison.assert_no_regressions(30.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "timings regressed by more than 10%")]
+    fn regression_fails() {
+        let before = Baseline::from_registry(&registry(&[("parse", 100)]));
+        let after = Baseline::from_registry(&registry(&[("parse", 200)]));
+        before.compare(&after).assert_no_regressions(10.0);
+    }
+}
@@ -0,0 +1,203 @@
+//! A minimal micro-benchmark runner, for when setting up a full benchmark
+//! harness is overkill.
+//!
+//! ```no_run
+//! use test_helpers::bench::bench;
+//!
+//! let result = bench("sum", || (0..1000u64).sum::<u64>());
+//! assert!(result.iterations > 0);
+//! ```
+
+use std::fmt;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use PrettyDuration;
+
+/// The most iterations run per sample, however fast the code being
+/// measured seems to be.
+const MAX_ITERS_PER_SAMPLE: u64 = 1 << 32;
+
+/// Configures a benchmark run.
+#[derive(Debug, Clone)]
+pub struct Bench {
+    warmup: Duration,
+    target: Duration,
+    samples: u32,
+}
+
+/// The result of a benchmark run. All durations are per iteration.
+///
+/// The `Display` impl formats this as a single line.
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub name: String,
+    /// The total number of iterations measured, excluding warmup.
+    pub iterations: u64,
+    /// The mean time per iteration of each sample.
+    pub samples: Vec<Duration>,
+    pub mean: Duration,
+    pub median: Duration,
+    pub std_dev: Duration,
+}
+
+/// Benchmarks `f` with the default settings, printing the result to stderr
+/// and returning it.
+pub fn bench<T, F: FnMut() -> T>(name: &str, f: F) -> BenchResult {
+    Bench::new().run(name, f)
+}
+
+impl Bench {
+    pub fn new() -> Self {
+        Bench {
+            warmup: Duration::from_millis(300),
+            target: Duration::from_secs(1),
+            samples: 30,
+        }
+    }
+
+    /// Sets how long `f` is run before measurement starts.
+    pub fn warmup(mut self, warmup: Duration) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Sets roughly how long measurement should take; the number of
+    /// iterations is scaled to fit.
+    pub fn target_time(mut self, target: Duration) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Sets the number of samples taken. Each sample runs `f` many times.
+    pub fn samples(mut self, samples: u32) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Benchmarks `f`, printing the result to stderr and returning it.
+    pub fn run<T, F: FnMut() -> T>(&self, name: &str, f: F) -> BenchResult {
+        let result = self.measure(name, f);
+        eprintln!("{}", result);
+        result
+    }
+
+    /// Benchmarks `f`, returning the result without printing it.
+    pub fn measure<T, F: FnMut() -> T>(&self, name: &str, mut f: F) -> BenchResult {
+        // warm up, and estimate the time per iteration as we go.
+        let start = Instant::now();
+        let mut warmup_iters = 0u64;
+        loop {
+            black_box(f());
+            warmup_iters += 1;
+            if start.elapsed() >= self.warmup {
+                break;
+            }
+        }
+        let per_iter = start.elapsed().as_secs_f64() / warmup_iters as f64;
+        let per_sample = self.target.as_secs_f64() / self.samples as f64;
+        let iters_per_sample = iters_per_sample(per_iter, per_sample);
+
+        let mut samples = Vec::with_capacity(self.samples as usize);
+        for _ in 0..self.samples {
+            let start = Instant::now();
+            for _ in 0..iters_per_sample {
+                black_box(f());
+            }
+            let elapsed = start.elapsed().as_secs_f64();
+            samples.push(Duration::from_secs_f64(elapsed / iters_per_sample as f64));
+        }
+        BenchResult::new(name, iters_per_sample.saturating_mul(self.samples as u64), samples)
+    }
+}
+
+/// Returns how many iterations fit in a sample, given estimates in seconds
+/// of the time per iteration and per sample.
+fn iters_per_sample(per_iter: f64, per_sample: f64) -> u64 {
+    // with a coarse clock and no warmup, an iteration can appear to take no
+    // time at all
+    let per_iter = per_iter.max(1e-9);
+    ((per_sample / per_iter) as u64).clamp(1, MAX_ITERS_PER_SAMPLE)
+}
+
+impl Default for Bench {
+    fn default() -> Self {
+        Bench::new()
+    }
+}
+
+impl BenchResult {
+    fn new(name: &str, iterations: u64, samples: Vec<Duration>) -> Self {
+        let n = samples.len() as f64;
+        let mean = samples.iter().map(Duration::as_secs_f64).sum::<f64>() / n;
+        let variance = if samples.len() > 1 {
+            samples.iter()
+                .map(|d| (d.as_secs_f64() - mean).powi(2))
+                .sum::<f64>() / (n - 1.0)
+        } else {
+            0.0
+        };
+        let mut sorted = samples.clone();
+        sorted.sort();
+        let mid = sorted.len() / 2;
+        let median = if sorted.len() % 2 == 0 {
+            (sorted[mid - 1] + sorted[mid]) / 2
+        } else {
+            sorted[mid]
+        };
+        BenchResult {
+            name: name.to_owned(),
+            iterations,
+            samples,
+            mean: Duration::from_secs_f64(mean),
+            median,
+            std_dev: Duration::from_secs_f64(variance.sqrt()),
+        }
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}/iter (median {}, std dev {}, {} iterations)", self.name,
+               PrettyDuration::new(self.mean), PrettyDuration::new(self.median),
+               PrettyDuration::new(self.std_dev), self.iterations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statistics() {
+        let samples = [4, 1, 3, 2].iter().map(|&us| Duration::from_micros(us)).collect();
+        let result = BenchResult::new("stats", 40, samples);
+        assert_eq!(result.mean, Duration::from_micros(2) + Duration::from_nanos(500));
+        assert_eq!(result.median, Duration::from_micros(2) + Duration::from_nanos(500));
+        // sample std dev of 1..=4 is ~1.291
+        assert_eq!(result.std_dev.as_nanos(), 1291);
+        assert_eq!(result.to_string(),
+                   "stats: 2us/iter (median 2us, std dev 1us, 40 iterations)");
+    }
+
+    #[test]
+    fn iterations() {
+        assert_eq!(iters_per_sample(1e-3, 1e-2), 10);
+        assert_eq!(iters_per_sample(1.0, 1e-2), 1);
+        assert_eq!(iters_per_sample(0.0, 1e-2), 10_000_000);
+        assert_eq!(iters_per_sample(0.0, 1e6), MAX_ITERS_PER_SAMPLE);
+    }
+
+    #[test]
+    fn run() {
+        let mut calls = 0u64;
+        let result = Bench::new()
+            .warmup(Duration::from_millis(1))
+            .target_time(Duration::from_millis(10))
+            .samples(5)
+            .measure("count", || calls += 1);
+        assert_eq!(result.samples.len(), 5);
+        assert!(result.iterations >= 5);
+        assert!(calls > result.iterations);
+    }
+}
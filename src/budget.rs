@@ -0,0 +1,178 @@
+//! Asserting that code finishes within a time budget.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clock::{self, Clock};
+use {CowStr, PrettyDuration};
+
+/// Asserts that evaluating an expression takes no longer than a limit,
+/// returning the expression's value.
+///
+/// ```
+/// # #[macro_use] extern crate test_helpers;
+/// # fn main() {
+/// use std::time::Duration;
+///
+/// let sum = assert_faster_than!(Duration::from_secs(1), {
+///     (0..1000u64).sum::<u64>()
+/// });
+/// # assert_eq!(sum, 499500);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! assert_faster_than {
+    ($limit:expr, $body:expr) => {{
+        let budget = $crate::BudgetTimer::new(concat!("block at ", file!(), ":", line!()),
+                                              $limit);
+        let value = $body;
+        if let Err(e) = budget.finish() {
+            panic!("assertion failed: {}", e);
+        }
+        value
+    }};
+}
+
+/// A guard that fails if it is not dropped within a time limit.
+///
+/// By default, dropping a `BudgetTimer` after its limit has passed panics
+/// (unless the thread is already panicking); with `non_fatal`, the failure
+/// is printed to stderr instead. `finish` checks the budget without
+/// panicking.
+pub struct BudgetTimer {
+    label: CowStr,
+    limit: Duration,
+    start: Instant,
+    clock: Option<Arc<dyn Clock>>,
+    fatal: bool,
+    finished: bool,
+}
+
+/// The error when a `BudgetTimer` runs over its limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetExceeded {
+    pub label: CowStr,
+    pub limit: Duration,
+    pub elapsed: Duration,
+}
+
+impl BudgetTimer {
+    /// Starts a timer that should be dropped within `limit`, reading the
+    /// time from the default clock.
+    pub fn new<S: Into<CowStr>>(label: S, limit: Duration) -> Self {
+        let clock = clock::default_clock();
+        BudgetTimer {
+            label: label.into(),
+            limit,
+            start: clock::now(clock.as_ref()),
+            clock,
+            fatal: true,
+            finished: false,
+        }
+    }
+
+    /// Reads the time from `clock` instead of from the default clock. This
+    /// restarts the timer.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.start = clock.now();
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Prints a message to stderr, instead of panicking, if the timer is
+    /// dropped after its limit.
+    pub fn non_fatal(mut self) -> Self {
+        self.fatal = false;
+        self
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        clock::now(self.clock.as_ref()) - self.start
+    }
+
+    /// Returns the time elapsed so far, or an error if it is over the
+    /// limit.
+    pub fn check(&self) -> Result<Duration, BudgetExceeded> {
+        let elapsed = self.elapsed();
+        if elapsed > self.limit {
+            Err(BudgetExceeded { label: self.label.clone(), limit: self.limit, elapsed })
+        } else {
+            Ok(elapsed)
+        }
+    }
+
+    /// Stops the timer, returning the time elapsed, or an error if it is
+    /// over the limit.
+    pub fn finish(mut self) -> Result<Duration, BudgetExceeded> {
+        self.finished = true;
+        self.check()
+    }
+}
+
+impl Drop for BudgetTimer {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let Err(e) = self.check() {
+            if self.fatal && !thread::panicking() {
+                panic!("{}", e);
+            }
+            eprintln!("{}", e);
+        }
+    }
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} took {}, over its budget of {}", self.label,
+               PrettyDuration::new(self.elapsed), PrettyDuration::new(self.limit))
+    }
+}
+
+impl Error for BudgetExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::MockClock;
+
+    #[test]
+    fn within_budget() {
+        let clock = Arc::new(MockClock::new());
+        let budget = BudgetTimer::new("parse", Duration::from_millis(50))
+            .with_clock(clock.clone());
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(budget.check(), Ok(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn over_budget() {
+        let clock = Arc::new(MockClock::new());
+        let budget = BudgetTimer::new("parse", Duration::from_millis(50))
+            .with_clock(clock.clone());
+        clock.advance(Duration::from_micros(62300));
+        let err = budget.finish().unwrap_err();
+        assert_eq!(err.to_string(), "parse took 62.3ms, over its budget of 50.0ms");
+    }
+
+    #[test]
+    #[should_panic(expected = "slow took 1.0s, over its budget of 1.0ms")]
+    fn panics_on_drop() {
+        let clock = Arc::new(MockClock::new());
+        let _budget = BudgetTimer::new("slow", Duration::from_millis(1))
+            .with_clock(clock.clone());
+        clock.advance(Duration::from_secs(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "assertion failed: block at src/budget.rs")]
+    fn assert_macro() {
+        assert_faster_than!(Duration::from_nanos(1), {
+            thread::sleep(Duration::from_millis(1));
+        });
+    }
+}
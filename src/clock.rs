@@ -0,0 +1,116 @@
+//! Sources of time for timers.
+//!
+//! Timers read the time from a `Clock`. By default this is the system's
+//! monotonic clock, but a `MockClock` can be used instead, either for a
+//! single timer (`TimerBuilder::clock`) or for all timers
+//! (`set_default_clock`), so that code which depends on timings can be
+//! tested deterministically.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+static DEFAULT_CLOCK: RwLock<Option<Arc<dyn Clock>>> = RwLock::new(None);
+
+/// A monotonic source of `Instant`s.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock, via `Instant::now`. This is the default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+/// A clock that only moves when it is told to.
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use test_helpers::BlockTimer;
+/// use test_helpers::clock::MockClock;
+///
+/// let clock = Arc::new(MockClock::new());
+/// let timer = BlockTimer::builder("mocked").clock(clock.clone()).start();
+/// clock.advance(Duration::from_millis(5));
+/// # if test_helpers::toggle::STATIC_ENABLED {
+/// assert_eq!(timer.elapsed(), Duration::from_millis(5));
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MockClock {
+    base: Instant,
+    offset_nanos: AtomicU64,
+}
+
+/// Sets the clock used by timers that were not given one explicitly.
+pub fn set_default_clock<C: Clock + 'static>(clock: C) {
+    *DEFAULT_CLOCK.write().unwrap() = Some(Arc::new(clock));
+}
+
+/// Restores the default clock to `SystemClock`.
+pub fn reset_default_clock() {
+    *DEFAULT_CLOCK.write().unwrap() = None;
+}
+
+/// Returns the default clock, or `None` if it is the system clock.
+pub(crate) fn default_clock() -> Option<Arc<dyn Clock>> {
+    DEFAULT_CLOCK.read().unwrap().clone()
+}
+
+/// Reads the time from `clock`, or the system clock if it is `None`.
+pub(crate) fn now(clock: Option<&Arc<dyn Clock>>) -> Instant {
+    match clock {
+        Some(clock) => clock.now(),
+        None => Instant::now(),
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl MockClock {
+    /// Creates a clock stopped at the current time.
+    pub fn new() -> Self {
+        MockClock {
+            base: Instant::now(),
+            offset_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// Moves the clock forward by `d`.
+    pub fn advance(&self, d: Duration) {
+        self.offset_nanos.fetch_add(d.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    /// The total time this clock has been advanced.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.offset_nanos.load(Ordering::SeqCst))
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.base + self.elapsed()
+    }
+}
+
+impl<T: Clock + ?Sized> Clock for Arc<T> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+}
+
+impl<T: Clock + ?Sized> Clock for &'static T {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+}
@@ -0,0 +1,221 @@
+//! Structured records of completed timers, and export as JSON Lines.
+
+use std::fmt::{self, Write as FmtWrite};
+use std::io::Write;
+use std::sync::Mutex;
+
+use json;
+use report::{Lap, TimerReport};
+use sink::TimerSink;
+use value::Value;
+use CowStr;
+
+/// A flat, structured record of a single completed timer.
+///
+/// A `TimerReport` can be converted into one event per timer with
+/// `TimerReport::events`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimingEvent {
+    pub label: CowStr,
+    /// When the timer started, in nanoseconds since the first timer in this
+    /// process was started.
+    pub start_ns: u64,
+    pub duration_ns: u64,
+    pub thread_id: u64,
+    pub thread_name: Option<String>,
+    /// How many timers this one was nested inside.
+    pub depth: usize,
+    pub metadata: Vec<(CowStr, Value)>,
+    /// The timer's laps, in the order they were recorded.
+    pub laps: Vec<Lap>,
+}
+
+/// Writes every completed timer, including nested timers, to the wrapped
+/// writer as a line of JSON.
+pub struct JsonLinesSink<W> {
+    inner: Mutex<W>,
+}
+
+impl TimingEvent {
+    pub(crate) fn from_report(report: &TimerReport, depth: usize) -> Self {
+        let mut metadata: Vec<(CowStr, Value)> = Vec::new();
+        if report.count > 1 {
+            metadata.push(("count".into(), report.count.into()));
+        }
+        for (key, value) in &report.fields {
+            metadata.push((format!("field.{}", key).into(), value.clone()));
+        }
+        if let Some(location) = report.location {
+            metadata.push(("module".into(), location.module_path.into()));
+            metadata.push(("file".into(), location.file.into()));
+            metadata.push(("line".into(), location.line.into()));
+        }
+        if report.pauses > 0 {
+            metadata.push(("pauses".into(), report.pauses.into()));
+            metadata.push(("paused_ns".into(), nanos(report.paused.as_nanos()).into()));
+        }
+        if let Some(cpu_time) = report.cpu_time {
+            metadata.push(("cpu_ns".into(), nanos(cpu_time.as_nanos()).into()));
+        }
+        if let Some(allocations) = report.allocations {
+            metadata.push(("allocations".into(), allocations.count.into()));
+            metadata.push(("bytes_allocated".into(), allocations.bytes_allocated.into()));
+            metadata.push(("bytes_freed".into(), allocations.bytes_freed.into()));
+            metadata.push(("peak_bytes".into(), allocations.peak.into()));
+        }
+        if let Some(polls) = report.polls {
+            metadata.push(("polls".into(), polls.count.into()));
+            metadata.push(("busy_ns".into(), nanos(polls.busy.as_nanos()).into()));
+        }
+        if report.is_slow() {
+            metadata.push(("slow".into(), true.into()));
+        }
+        TimingEvent {
+            label: report.label.clone(),
+            start_ns: nanos(report.start_offset.as_nanos()),
+            duration_ns: nanos(report.elapsed.as_nanos()),
+            thread_id: report.thread.id,
+            thread_name: report.thread.name.as_ref().map(|s| s.to_string()),
+            depth,
+            metadata,
+            laps: report.laps.clone(),
+        }
+    }
+
+    /// Returns this event as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out).unwrap();
+        out
+    }
+
+    fn write_json(&self, out: &mut String) -> fmt::Result {
+        out.push_str("{\"label\":");
+        json::write_str(out, &self.label)?;
+        write!(out, ",\"start_ns\":{},\"duration_ns\":{},\"thread_id\":{},\"thread_name\":",
+               self.start_ns, self.duration_ns, self.thread_id)?;
+        match self.thread_name {
+            Some(ref name) => json::write_str(out, name)?,
+            None => out.push_str("null"),
+        }
+        write!(out, ",\"depth\":{},\"metadata\":{{", self.depth)?;
+        for (i, (key, value)) in self.metadata.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            json::write_str(out, key)?;
+            out.push(':');
+            json::write_value(out, value)?;
+        }
+        out.push_str("},\"laps\":");
+        write_laps(out, &self.laps)?;
+        out.push('}');
+        Ok(())
+    }
+}
+
+/// Writes `laps` as a JSON array of objects, each with the lap's `label`,
+/// `elapsed_ns` and `cumulative_ns`.
+pub(crate) fn write_laps(out: &mut String, laps: &[Lap]) -> fmt::Result {
+    out.push('[');
+    for (i, lap) in laps.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str("{\"label\":");
+        json::write_str(out, &lap.label)?;
+        write!(out, ",\"elapsed_ns\":{},\"cumulative_ns\":{}}}",
+               nanos(lap.elapsed.as_nanos()), nanos(lap.cumulative.as_nanos()))?;
+    }
+    out.push(']');
+    Ok(())
+}
+
+impl<W: Write + Send> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        JsonLinesSink { inner: Mutex::new(writer) }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> TimerSink for JsonLinesSink<W> {
+    fn record(&self, report: &TimerReport) {
+        let mut writer = self.inner.lock().unwrap();
+        for event in report.events() {
+            let _ = writeln!(writer, "{}", event.to_json());
+        }
+    }
+}
+
+impl<W> fmt::Debug for JsonLinesSink<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JsonLinesSink").finish()
+    }
+}
+
+fn nanos(n: u128) -> u64 {
+    n.min(u64::MAX as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    #[cfg(not(timing_off))]
+    use std::sync::Arc;
+    #[cfg(not(timing_off))]
+    use BlockTimer;
+
+    #[test]
+    fn json() {
+        let event = TimingEvent {
+            label: "say \"hi\"".into(),
+            start_ns: 10,
+            duration_ns: 1500,
+            thread_id: 2,
+            thread_name: None,
+            depth: 1,
+            metadata: vec![("pauses".into(), 2u32.into()), ("slow".into(), true.into())],
+            laps: Vec::new(),
+        };
+        assert_eq!(event.to_json(),
+                   "{\"label\":\"say \\\"hi\\\"\",\"start_ns\":10,\"duration_ns\":1500,\
+                    \"thread_id\":2,\"thread_name\":null,\"depth\":1,\
+                    \"metadata\":{\"pauses\":2,\"slow\":true},\"laps\":[]}");
+    }
+
+    #[test]
+    fn repeated_laps() {
+        let lap = |elapsed, cumulative| Lap {
+            label: "iter".into(),
+            elapsed: Duration::from_nanos(elapsed),
+            cumulative: Duration::from_nanos(cumulative),
+        };
+        let mut out = String::new();
+        write_laps(&mut out, &[lap(25, 25), lap(3, 28)]).unwrap();
+        assert_eq!(out, "[{\"label\":\"iter\",\"elapsed_ns\":25,\"cumulative_ns\":25},\
+                         {\"label\":\"iter\",\"elapsed_ns\":3,\"cumulative_ns\":28}]");
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn json_lines_sink() {
+        let sink = Arc::new(JsonLinesSink::new(Vec::new()));
+        {
+            let _outer = BlockTimer::with_sink("outer", sink.clone());
+            BlockTimer::new("inner").with_field("file", "foo.rs");
+        }
+        let sink = Arc::try_unwrap(sink).unwrap();
+        let text = String::from_utf8(sink.into_inner()).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("{\"label\":\"outer\""));
+        assert!(lines[0].contains("\"depth\":0"));
+        assert!(lines[1].starts_with("{\"label\":\"inner\""));
+        assert!(lines[1].contains("\"depth\":1"));
+        assert!(lines[1].contains("\"metadata\":{\"field.file\":\"foo.rs\""));
+    }
+}
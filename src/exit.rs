@@ -0,0 +1,65 @@
+//! Writing output files, and running code when the process exits.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+
+extern "C" {
+    fn atexit(callback: extern "C" fn()) -> c_int;
+}
+
+/// Registers `callback` to be run when the process exits normally, that is
+/// by returning from `main` or by calling `std::process::exit`.
+pub(crate) fn at_exit(callback: extern "C" fn()) {
+    unsafe {
+        atexit(callback);
+    }
+}
+
+/// Creates the file at `path`, and any missing parent directories, and
+/// passes a buffered writer for it to `write`.
+pub(crate) fn write_file<P, F>(path: P, write: F) -> io::Result<()>
+    where P: AsRef<Path>,
+          F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    let path = path.as_ref();
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    write(&mut writer)?;
+    writer.flush()
+}
+
+/// The path that some global output should be saved to when the process
+/// exits, as set by the `save_at_exit` functions.
+pub(crate) struct SaveAtExit {
+    path: Mutex<Option<PathBuf>>,
+    register: Once,
+}
+
+impl SaveAtExit {
+    pub(crate) const fn new() -> Self {
+        SaveAtExit { path: Mutex::new(None), register: Once::new() }
+    }
+
+    /// Sets the path, registering `callback` the first time this is called.
+    /// The callback should call `save`.
+    pub(crate) fn set_path(&'static self, path: PathBuf, callback: extern "C" fn()) {
+        *self.path.lock().unwrap() = Some(path);
+        self.register.call_once(|| at_exit(callback));
+    }
+
+    /// Calls `save` with the path, if one is set, printing any error.
+    pub(crate) fn save<F>(&self, what: &str, save: F)
+        where F: FnOnce(&Path) -> io::Result<()>
+    {
+        if let Some(path) = self.path.lock().unwrap().as_ref() {
+            if let Err(e) = save(path) {
+                eprintln!("failed to write {} to {}: {}", what, path.display(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Choosing which timers run, by label or module path.
+//!
+//! A filter is a comma-separated list of patterns, in the style of
+//! `RUST_LOG`. For example, `TEST_HELPERS_TIMERS=parse*,render::layout,-noisy`
+//! enables timers whose label starts with `parse`, and timers labelled or
+//! created inside `render::layout`, except for those matching `noisy`.
+//!
+//! - A pattern containing `*` must match the whole label or module path,
+//!   with each `*` matching any run of characters (including `::`).
+//! - Any other pattern matches as a prefix.
+//! - A pattern starting with `-` excludes the timers it matches. Exclusions
+//!   take precedence over inclusions.
+//! - If there are no inclusions, every timer that isn't excluded runs.
+//!
+//! Timers started by the macros match against both their label and the
+//! module they were created in; other timers match only their label.
+
+use std::env;
+use std::sync::{Arc, RwLock};
+
+use toggle::{self, TimingMode};
+
+pub(crate) const FILTER_ENV: &str = "TEST_HELPERS_TIMERS";
+
+static FILTER: RwLock<Option<Arc<Filter>>> = RwLock::new(None);
+
+/// A parsed filter specification.
+///
+/// ```
+/// use test_helpers::filter::Filter;
+///
+/// let filter = Filter::parse("parse*,render::layout,-noisy");
+/// assert!(filter.matches("parse header", None));
+/// assert!(filter.matches("lines", Some("render::layout::text")));
+/// assert!(!filter.matches("paint", Some("render::paint")));
+/// assert!(!filter.matches("noisy", Some("render::layout")));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pattern {
+    Prefix(String),
+    Glob(String),
+}
+
+impl Filter {
+    /// Parses a filter specification. Empty patterns are ignored.
+    pub fn parse(spec: &str) -> Filter {
+        let mut filter = Filter::default();
+        for pattern in spec.split(',').map(str::trim) {
+            let (rules, pattern) = match pattern.strip_prefix('-') {
+                Some(pattern) => (&mut filter.exclude, pattern.trim()),
+                None => (&mut filter.include, pattern),
+            };
+            if pattern.is_empty() {
+                continue;
+            }
+            rules.push(if pattern.contains('*') {
+                Pattern::Glob(pattern.to_owned())
+            } else {
+                Pattern::Prefix(pattern.to_owned())
+            });
+        }
+        filter
+    }
+
+    /// Returns `true` if a timer with this label, created in `module_path`,
+    /// should run.
+    pub fn matches(&self, label: &str, module_path: Option<&str>) -> bool {
+        let matches = |pattern: &Pattern| {
+            pattern.matches(label) || module_path.map(|path| pattern.matches(path)).unwrap_or(false)
+        };
+        if self.exclude.iter().any(&matches) {
+            return false;
+        }
+        self.include.is_empty() || self.include.iter().any(&matches)
+    }
+}
+
+impl Pattern {
+    fn matches(&self, s: &str) -> bool {
+        match *self {
+            Pattern::Prefix(ref prefix) => s.starts_with(prefix.as_str()),
+            Pattern::Glob(ref glob) => glob_matches(glob, s),
+        }
+    }
+}
+
+/// Matches `s` against a pattern where `*` matches any run of characters.
+fn glob_matches(glob: &str, s: &str) -> bool {
+    let mut parts = glob.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match s.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let mut parts = parts.collect::<Vec<_>>();
+    let last = parts.pop().unwrap_or("");
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Replaces the filter read from `TEST_HELPERS_TIMERS`, and switches to
+/// `TimingMode::Filter`.
+pub fn set_filter(filter: Filter) {
+    *FILTER.write().unwrap() = Some(Arc::new(filter));
+    toggle::set_timing_mode(TimingMode::Filter);
+}
+
+/// Returns the current filter, reading it from the environment on first
+/// use.
+pub fn current() -> Arc<Filter> {
+    if let Some(ref filter) = *FILTER.read().unwrap() {
+        return filter.clone();
+    }
+    let mut slot = FILTER.write().unwrap();
+    slot.get_or_insert_with(|| {
+        Arc::new(Filter::parse(&env::var(FILTER_ENV).unwrap_or_default()))
+    }).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let filter = Filter::parse(" parse*, render::layout,,-noisy, - ");
+        assert_eq!(filter.include, vec![Pattern::Glob("parse*".into()),
+                                        Pattern::Prefix("render::layout".into())]);
+        assert_eq!(filter.exclude, vec![Pattern::Prefix("noisy".into())]);
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("parse*", "parse"));
+        assert!(glob_matches("parse*", "parser::header"));
+        assert!(!glob_matches("parse*", "reparse"));
+        assert!(glob_matches("*::layout", "app::render::layout"));
+        assert!(!glob_matches("*::layout", "app::render::layout::text"));
+        assert!(glob_matches("a*b*c", "a-b-b-c"));
+        assert!(!glob_matches("ab*ba", "aba"));
+        assert!(glob_matches("*", ""));
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let filter = Filter::parse("parse*,render::layout,-noisy");
+        assert!(filter.matches("parse header", None));
+        assert!(filter.matches("render::layout::lines", None));
+        assert!(filter.matches("line 3", Some("render::layout::text")));
+        assert!(!filter.matches("paint", Some("render::paint")));
+        assert!(!filter.matches("parse", Some("noisy::lexer")));
+        assert!(!filter.matches("noisy", Some("render::layout")));
+
+        let filter = Filter::parse("-noisy");
+        assert!(filter.matches("anything", None));
+        assert!(!filter.matches("noisy loop", None));
+        assert!(Filter::parse("").matches("anything", None));
+    }
+}
@@ -0,0 +1,142 @@
+//! Export of nested timers in the "folded stacks" format read by
+//! `flamegraph.pl` and `inferno`, as a cheap instrumented flamegraph.
+//!
+//! Each line of the output is a `;`-separated path of nested timer labels
+//! followed by the total self time, in nanoseconds, spent in that path:
+//!
+//! ```text
+//! outer;inner;leaf 12345
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use exit;
+use report::TimerReport;
+use sink::{self, TimerSink};
+
+static GLOBAL: FoldedStacks = FoldedStacks::new();
+static SAVE_AT_EXIT: exit::SaveAtExit = exit::SaveAtExit::new();
+
+/// Aggregates the self time of nested timers by their stack of labels.
+///
+/// The `Display` impl writes the folded stacks, one per line.
+#[derive(Debug, Default)]
+pub struct FoldedStacks {
+    stacks: Mutex<BTreeMap<String, u64>>,
+}
+
+/// Returns the global stack aggregator.
+pub fn global() -> &'static FoldedStacks {
+    &GLOBAL
+}
+
+/// Makes the global aggregator the default sink, so that timers without a
+/// sink of their own are aggregated instead of printed.
+pub fn enable() {
+    sink::set_default_sink(global());
+}
+
+/// Arranges for the global aggregator to be saved to `path` when the
+/// process exits normally. If this is called more than once, the last path
+/// is used.
+pub fn save_at_exit<P: Into<PathBuf>>(path: P) {
+    extern "C" fn save_global() {
+        SAVE_AT_EXIT.save("folded stacks", |path| global().save(path));
+    }
+
+    SAVE_AT_EXIT.set_path(path.into(), save_global);
+}
+
+impl FoldedStacks {
+    pub const fn new() -> Self {
+        FoldedStacks { stacks: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Returns the total self time, in nanoseconds, recorded for each stack.
+    pub fn stacks(&self) -> Vec<(String, u64)> {
+        self.stacks.lock().unwrap().iter()
+            .map(|(stack, nanos)| (stack.clone(), *nanos))
+            .collect()
+    }
+
+    /// Discards all recorded stacks.
+    pub fn clear(&self) {
+        self.stacks.lock().unwrap().clear();
+    }
+
+    /// Writes the folded stacks to `path`, creating any missing parent
+    /// directories.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        exit::write_file(path, |writer| self.write_to(writer))
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(writer, "{}", self)
+    }
+
+    fn add(&self, stacks: &mut BTreeMap<String, u64>, prefix: &str,
+           report: &TimerReport) {
+        let mut path = String::with_capacity(prefix.len() + report.label.len() + 1);
+        path.push_str(prefix);
+        if !prefix.is_empty() {
+            path.push(';');
+        }
+        // the separators of the format can't appear in a frame
+        path.extend(report.label.chars().map(|c| match c {
+            ';' => ':',
+            '\n' | '\r' => ' ',
+            c => c,
+        }));
+        let nanos = report.self_time().as_nanos().min(u64::MAX as u128) as u64;
+        if nanos > 0 {
+            *stacks.entry(path.clone()).or_insert(0) += nanos;
+        }
+        for child in &report.children {
+            self.add(stacks, &path, child);
+        }
+    }
+}
+
+impl TimerSink for FoldedStacks {
+    fn record(&self, report: &TimerReport) {
+        let mut stacks = self.stacks.lock().unwrap();
+        self.add(&mut stacks, "", report);
+    }
+}
+
+impl fmt::Display for FoldedStacks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (stack, nanos) in self.stacks.lock().unwrap().iter() {
+            writeln!(f, "{} {}", stack, nanos)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use report::tests::report;
+
+    #[test]
+    fn folded() {
+        let stacks = FoldedStacks::new();
+        let tree = report("main", 10, vec![
+            report("parse", 4, vec![report("lex;er", 1, vec![])]),
+            report("render", 6, vec![]),
+        ]);
+        stacks.record(&tree);
+        stacks.record(&report("main", 1, vec![]));
+        let expected = "\
+main 1000000
+main;parse 3000000
+main;parse;lex:er 1000000
+main;render 6000000
+";
+        assert_eq!(stacks.to_string(), expected);
+    }
+}
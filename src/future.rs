@@ -0,0 +1,185 @@
+//! Timing for futures.
+//!
+//! A `BlockTimer` held across an `.await` measures the time until the
+//! enclosing scope ends, including any time the task spends suspended. A
+//! `Timed` future instead reports both the time from its first poll until
+//! it completes, and the time actually spent inside `poll`.
+
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use alloc;
+use clock::{self, Clock};
+use report::{push_child, Polls, TimerReport};
+use sink::TimerSink;
+use {deliver, epoch, stack, threshold, toggle, CowStr};
+
+/// A future that reports how long the future it wraps took to complete.
+///
+/// When the inner future completes, a report is passed to the sink (or
+/// the default sink) in the same way as for a `BlockTimer`; its `elapsed`
+/// time is measured from the first poll, and its `polls` records the number
+/// of polls and the total time spent polling. Timers that finish during a
+/// poll are nested under the future's report. Nothing is reported if the
+/// future is dropped before it completes, or if timing is switched off.
+///
+/// Created with `TimedExt::timed`.
+pub struct Timed<F> {
+    inner: F,
+    label: CowStr,
+    enabled: bool,
+    sink: Option<Arc<dyn TimerSink>>,
+    clock: Option<Arc<dyn Clock>>,
+    first_poll: Option<Instant>,
+    busy: Duration,
+    polls: u32,
+    children: Vec<TimerReport>,
+}
+
+/// An extension trait for timing futures.
+pub trait TimedExt: Future + Sized {
+    /// Wraps this future so that it is reported with `label` when it
+    /// completes.
+    fn timed<S: Into<CowStr>>(self, label: S) -> Timed<Self> {
+        Timed::new(self, label)
+    }
+}
+
+impl<F: Future> TimedExt for F {}
+
+impl<F: Future> Timed<F> {
+    pub fn new<S: Into<CowStr>>(inner: F, label: S) -> Self {
+        let label = label.into();
+        Timed {
+            inner,
+            enabled: toggle::label_enabled(&label, None),
+            label,
+            sink: None,
+            clock: clock::default_clock(),
+            first_poll: None,
+            busy: Duration::default(),
+            polls: 0,
+            children: Vec::new(),
+        }
+    }
+
+    /// Reports to `sink`, instead of to the default sink.
+    pub fn with_sink<T: TimerSink + 'static>(mut self, sink: T) -> Self {
+        self.sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Reads the time from `clock`, instead of from the default clock.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+}
+
+impl<F: Future> Future for Timed<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        // safety: `inner` is structurally pinned; it is never moved out of
+        // `self`, and `Timed` has no `Drop` impl.
+        let this = unsafe { self.get_unchecked_mut() };
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+        if !this.enabled {
+            return inner.poll(cx);
+        }
+
+        epoch();
+        let start = clock::now(this.clock.as_ref());
+        let sink = this.sink.clone();
+        let entry = alloc::untracked(|| stack::push(sink));
+        let result = inner.poll(cx);
+        let end = clock::now(this.clock.as_ref());
+        alloc::untracked(|| this.finish_poll(&entry, start, end, result.is_ready()));
+        result
+    }
+}
+
+impl<F> Timed<F> {
+    /// Records a poll, that ran from `start` to `end`, and reports the
+    /// future if it is `ready`.
+    fn finish_poll(&mut self, entry: &stack::Entry, start: Instant, end: Instant,
+                   ready: bool) {
+        let (children, parent) = stack::pop(entry);
+        let first_poll = *self.first_poll.get_or_insert(start);
+        self.busy += end - start;
+        self.polls += 1;
+        for child in children {
+            push_child(&mut self.children, child);
+        }
+
+        if ready {
+            let elapsed = end - first_poll;
+            if !threshold::below_min(elapsed, None) {
+                let mut report = TimerReport::new(self.label.clone(), first_poll, elapsed);
+                report.polls = Some(Polls { count: self.polls, busy: self.busy });
+                report.children = mem::take(&mut self.children);
+                deliver(report, parent, self.sink.as_ref());
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(timing_off)))]
+mod tests {
+    use super::*;
+    use std::task::{Wake, Waker};
+    use sink::Collector;
+    use BlockTimer;
+
+    /// Returns `Pending` the given number of times before completing.
+    struct YieldTimes(u32);
+
+    impl Future for YieldTimes {
+        type Output = &'static str;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<&'static str> {
+            BlockTimer::new("work");
+            if self.0 == 0 {
+                return Poll::Ready("done");
+            }
+            self.0 -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn timed_future() {
+        let collector = Arc::new(Collector::new());
+        let future = YieldTimes(2).timed("yield").with_sink(collector.clone());
+        assert_eq!(block_on(future), "done");
+        let report = &collector.take()[0];
+        assert_eq!(report.label, "yield");
+        let polls = report.polls.unwrap();
+        assert_eq!(polls.count, 3);
+        assert!(polls.busy <= report.elapsed);
+        assert_eq!(report.children.len(), 3);
+        assert_eq!(report.children[0].label, "work");
+    }
+}
@@ -0,0 +1,207 @@
+//! A log-bucketed histogram of durations, for percentile queries.
+
+use std::fmt;
+use std::time::Duration;
+
+use PrettyDuration;
+
+/// The number of buckets per power of two, as a power of two. Values are
+/// recorded with a relative error of at most 1 / 2^SUB_BITS.
+const SUB_BITS: u32 = 6;
+const SUB: u64 = 1 << SUB_BITS;
+
+/// The width, in characters, of the longest bar when rendering.
+const BAR_WIDTH: usize = 40;
+
+/// A histogram of durations, with bounded memory and a small relative
+/// error, in the manner of HdrHistogram.
+///
+/// Durations are recorded in nanoseconds, into buckets whose width grows
+/// with their value; each bucket spans about 1.6% of its value. The
+/// `Display` impl renders the histogram as ASCII bars, with one row per
+/// power of two.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram::default()
+    }
+
+    pub fn record(&mut self, d: Duration) {
+        let nanos = d.as_nanos().min(u64::MAX as u128) as u64;
+        let idx = bucket_index(nanos);
+        if idx >= self.counts.len() {
+            self.counts.resize(idx + 1, 0);
+        }
+        self.counts[idx] += 1;
+        if self.total == 0 || nanos < self.min {
+            self.min = nanos;
+        }
+        self.max = self.max.max(nanos);
+        self.total += 1;
+    }
+
+    /// The number of durations recorded.
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    pub fn min(&self) -> Duration {
+        Duration::from_nanos(self.min)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    /// Returns the duration below which `percentile` percent of the
+    /// recorded durations fall, such as `99.0` for the 99th percentile.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.total == 0 {
+            return Duration::default();
+        }
+        let rank = (percentile / 100.0 * self.total as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (idx, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let (low, high) = bucket_bounds(idx);
+                let mid = low + (high - low) / 2;
+                return Duration::from_nanos(mid.max(self.min).min(self.max));
+            }
+        }
+        self.max()
+    }
+
+    pub fn p50(&self) -> Duration {
+        self.percentile(50.0)
+    }
+
+    pub fn p90(&self) -> Duration {
+        self.percentile(90.0)
+    }
+
+    pub fn p99(&self) -> Duration {
+        self.percentile(99.0)
+    }
+
+    pub fn p999(&self) -> Duration {
+        self.percentile(99.9)
+    }
+
+    /// Returns the counts summed over each power of two, as
+    /// `(low, high, count)`, from the lowest nonempty one to the highest.
+    fn rows(&self) -> Vec<(u64, u64, u64)> {
+        let mut rows: Vec<(u64, u64, u64)> = Vec::new();
+        for (idx, &count) in self.counts.iter().enumerate() {
+            let (low, _) = bucket_bounds(idx);
+            let (row_low, row_high) = if low < SUB {
+                (0, SUB - 1)
+            } else {
+                let row_low = 1 << (63 - low.leading_zeros());
+                (row_low, row_low + (row_low - 1))
+            };
+            match rows.last_mut() {
+                Some(row) if row.0 == row_low => row.2 += count,
+                _ => rows.push((row_low, row_high, count)),
+            }
+        }
+        let first = rows.iter().position(|r| r.2 > 0).unwrap_or(rows.len());
+        rows.split_off(first)
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = self.rows();
+        let max_count = rows.iter().map(|r| r.2).max().unwrap_or(0);
+        let pretty = |nanos| PrettyDuration::new(Duration::from_nanos(nanos)).to_string();
+        for (i, &(low, high, count)) in rows.iter().enumerate() {
+            let bar = (count * BAR_WIDTH as u64).div_ceil(max_count) as usize;
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:>9} .. {:>9} | {:<width$} {}", pretty(low), pretty(high),
+                   "#".repeat(bar), count, width = BAR_WIDTH)?;
+        }
+        Ok(())
+    }
+}
+
+fn bucket_index(nanos: u64) -> usize {
+    if nanos < SUB {
+        return nanos as usize;
+    }
+    let msb = 63 - nanos.leading_zeros();
+    let shift = msb - SUB_BITS;
+    let sub = (nanos >> shift) - SUB;
+    (SUB + shift as u64 * SUB + sub) as usize
+}
+
+/// The smallest and largest values recorded in bucket `idx`.
+fn bucket_bounds(idx: usize) -> (u64, u64) {
+    let idx = idx as u64;
+    if idx < SUB {
+        return (idx, idx);
+    }
+    let shift = (idx - SUB) / SUB;
+    let sub = (idx - SUB) % SUB;
+    let low = (SUB + sub) << shift;
+    (low, low + ((1 << shift) - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets() {
+        for &n in &[0, 1, 63, 64, 65, 127, 128, 1000, 123_456_789, u64::MAX] {
+            let (low, high) = bucket_bounds(bucket_index(n));
+            assert!(low <= n && n <= high, "{} not in {}..{}", n, low, high);
+            assert!((high - low) as f64 <= n as f64 / SUB as f64);
+        }
+        assert_eq!(bucket_index(u64::MAX), 3775);
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut hist = Histogram::new();
+        for micros in 1..=1000 {
+            hist.record(Duration::from_micros(micros));
+        }
+        assert_eq!(hist.count(), 1000);
+        assert_eq!(hist.min(), Duration::from_micros(1));
+        assert_eq!(hist.max(), Duration::from_micros(1000));
+        let close = |actual: Duration, expected: u64| {
+            let error = (actual.as_nanos() as f64 - expected as f64).abs() / expected as f64;
+            assert!(error < 0.02, "{:?} != {}us", actual, expected / 1000);
+        };
+        close(hist.p50(), 500_000);
+        close(hist.p90(), 900_000);
+        close(hist.p99(), 990_000);
+        close(hist.p999(), 999_000);
+        assert_eq!(hist.percentile(100.0), hist.max());
+    }
+
+    #[test]
+    fn render() {
+        let mut hist = Histogram::new();
+        for _ in 0..4 {
+            hist.record(Duration::from_nanos(100));
+        }
+        hist.record(Duration::from_nanos(300));
+        let expected = [
+            format!("     64ns ..     127ns | {:<40} 4", "#".repeat(40)),
+            format!("    128ns ..     255ns | {:<40} 0", ""),
+            format!("    256ns ..     511ns | {:<40} 1", "#".repeat(10)),
+        ].join("\n");
+        assert_eq!(hist.to_string(), expected);
+    }
+}
@@ -1,15 +1,135 @@
 use std::fmt;
+#[cfg(not(timing_off))]
+use std::mem;
+use std::sync::{Arc, OnceLock};
 use std::time::{Instant, Duration};
 use std::borrow::Cow;
 
+#[cfg(not(timing_off))]
+use alloc::AllocScope;
+
+#[cfg(unix)]
+extern crate libc;
+#[cfg(feature = "log")]
+extern crate log;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "tracing")]
+extern crate tracing_subscriber;
+
+#[macro_use]
+mod macros;
+
+pub mod alloc;
+pub mod baseline;
+pub mod bench;
+pub mod budget;
+pub mod clock;
+pub mod cpu;
+pub mod event;
+pub mod filter;
+pub mod folded;
+pub mod future;
+pub mod histogram;
+pub mod registry;
+pub mod sink;
+#[cfg(feature = "tracing")]
+pub mod spans;
+pub mod toggle;
+pub mod trace;
+pub mod watchdog;
+mod exit;
+mod json;
+#[cfg(timing_off)]
+mod noop;
+mod report;
+mod stack;
+mod threshold;
+mod value;
+
+pub use budget::{BudgetExceeded, BudgetTimer};
+#[cfg(timing_off)]
+pub use noop::{BlockTimer, TimerBuilder};
+pub use clock::Clock;
+pub use alloc::{AllocStats, CountingAllocator};
+pub use event::TimingEvent;
+#[doc(hidden)]
+pub use macros::{__function_path, __label, __type_name_of};
+pub use future::{Timed, TimedExt};
+pub use histogram::Histogram;
+pub use report::{Lap, Polls, SourceLocation, ThreadInfo, TimerReport, MAX_CHILDREN};
+pub use value::Value;
+pub use sink::{TimerSink, set_default_sink};
+pub use threshold::{set_default_min_duration, set_default_warn_after};
+pub use toggle::{TimingMode, set_timing_mode, timing_enabled};
+
 type CowStr = Cow<'static, str>;
 
+/// The instant that event timestamps are measured from.
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
 /// A multi-purpose timer, for debugging. When an instance is stopped or
-/// goes out of scope, the label and the elapsed time is printed to stderr.
+/// goes out of scope, the label and the elapsed time are passed to its
+/// sink; unless configured otherwise, this prints them to stderr.
+///
+/// Timers that are started and stopped while another timer is running on
+/// the same thread are reported together with that timer, as an indented
+/// tree, when the outermost timer finishes.
+#[cfg(not(timing_off))]
 pub struct BlockTimer {
+    /// `None` if timing is switched off.
+    running: Option<Running>,
+}
+
+/// The state of an enabled `BlockTimer`.
+#[cfg(not(timing_off))]
+struct Running {
     label: CowStr,
+    fields: Vec<(CowStr, Value)>,
     start: Instant,
     stopped: bool,
+    elapsed_at_stop: Duration,
+    entry: stack::Entry,
+    thread: u64,
+    paused_at: Option<Instant>,
+    paused: Duration,
+    pauses: u32,
+    cpu: Option<CpuTimer>,
+    alloc: Option<AllocScope>,
+    laps: Vec<Lap>,
+    sink: Option<Arc<dyn TimerSink>>,
+    clock: Option<Arc<dyn Clock>>,
+    min_duration: Option<Duration>,
+    warn_after: Option<Duration>,
+    location: Option<SourceLocation>,
+    #[cfg(feature = "tracing")]
+    span: spans::TimerSpan,
+}
+
+/// Configures a `BlockTimer` before starting it. Created with
+/// `BlockTimer::builder`.
+#[cfg(not(timing_off))]
+pub struct TimerBuilder {
+    label: CowStr,
+    fields: Vec<(CowStr, Value)>,
+    sink: Option<Arc<dyn TimerSink>>,
+    clock: Option<Arc<dyn Clock>>,
+    cpu_time: bool,
+    min_duration: Option<Duration>,
+    warn_after: Option<Duration>,
+    location: Option<SourceLocation>,
+}
+
+/// Tracks the CPU time used by the thread running a timer, excluding any
+/// time the timer was paused.
+#[cfg(not(timing_off))]
+struct CpuTimer {
+    start: Duration,
+    paused_at: Option<Duration>,
+    paused: Duration,
 }
 
 /// A struct which implements fmt::Display to provide a human-readable
@@ -21,30 +141,380 @@ pub struct PrettyDuration {
     nanos: u64,
 }
 
+#[cfg(not(timing_off))]
 impl BlockTimer {
     pub fn new<S: Into<CowStr>>(label: S) -> Self {
-        BlockTimer {
-            label: label.into(),
-            start: Instant::now(),
-            stopped: false,
+        TimerBuilder::new(label).start()
+    }
+
+    /// Creates a timer that reports to `sink`, instead of to the default
+    /// sink. If the timer is nested inside another, it is reported through
+    /// the outer timer's sink instead.
+    pub fn with_sink<S, T>(label: S, sink: T) -> Self
+        where S: Into<CowStr>,
+              T: TimerSink + 'static,
+    {
+        TimerBuilder::new(label).sink(sink).start()
+    }
+
+    /// Returns a builder, for configuring a timer before starting it.
+    pub fn builder<S: Into<CowStr>>(label: S) -> TimerBuilder {
+        TimerBuilder::new(label)
+    }
+
+    /// Returns a timer that does nothing, as used when timing is switched
+    /// off. See the `toggle` module.
+    #[inline]
+    pub fn disabled() -> Self {
+        BlockTimer { running: None }
+    }
+
+    /// Returns `true` if this timer is running or has run; `false` if it
+    /// was disabled.
+    pub fn is_enabled(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Attaches a key-value pair to the timer, to be included in its report.
+    ///
+    /// ```
+    /// use test_helpers::BlockTimer;
+    ///
+    /// let _timer = BlockTimer::new("parse")
+    ///     .with_field("file", "foo.rs")
+    ///     .with_field("bytes", 12040);
+    /// ```
+    pub fn with_field<K, V>(mut self, key: K, value: V) -> Self
+        where K: Into<CowStr>,
+              V: Into<Value>,
+    {
+        if let Some(ref mut timer) = self.running {
+            let field = (key.into(), value.into());
+            alloc::untracked(|| timer.fields.push(field));
+        }
+        self
+    }
+
+    /// Records an intermediate checkpoint, returning the time since the
+    /// previous one (or since the timer started). Each lap is included in
+    /// the timer's report. Once the timer has stopped, this does nothing
+    /// and returns zero.
+    pub fn lap<S: Into<CowStr>>(&mut self, label: S) -> Duration {
+        match self.running {
+            Some(ref mut timer) => {
+                let label = label.into();
+                alloc::untracked(|| timer.lap(label))
+            }
+            None => Duration::default(),
         }
     }
 
+    /// Pauses the timer. Time spent paused is excluded from the reported
+    /// time (and from any laps) until `resume` is called.
+    pub fn pause(&mut self) {
+        if let Some(ref mut timer) = self.running {
+            timer.pause();
+        }
+    }
+
+    /// Resumes a paused timer.
+    pub fn resume(&mut self) {
+        if let Some(ref mut timer) = self.running {
+            timer.resume();
+        }
+    }
+
+    /// Returns the time the timer has been running, excluding any time it
+    /// was paused. Once the timer is stopped, this is the reported time.
+    ///
+    /// If timing is switched off, this is always zero.
+    pub fn elapsed(&self) -> Duration {
+        match self.running {
+            Some(ref timer) => timer.elapsed(),
+            None => Duration::default(),
+        }
+    }
+
+    /// Stops the timer and reports it. Calling this more than once has no
+    /// effect.
     pub fn stop(&mut self) {
+        if let Some(ref mut timer) = self.running {
+            alloc::untracked(|| timer.stop());
+        }
+    }
+}
+
+#[cfg(not(timing_off))]
+impl Running {
+    fn lap(&mut self, label: CowStr) -> Duration {
+        if self.stopped {
+            return Duration::default();
+        }
+        let cumulative = self.active_time();
+        let previous = self.laps.last().map(|lap| lap.cumulative).unwrap_or_default();
+        let elapsed = cumulative - previous;
+        self.laps.push(Lap { label, elapsed, cumulative });
+        elapsed
+    }
+
+    fn pause(&mut self) {
+        if self.paused_at.is_none() && !self.stopped {
+            self.paused_at = Some(self.now());
+            self.pauses += 1;
+            if let Some(cpu) = self.cpu.as_mut() {
+                cpu.paused_at = cpu::thread_cpu_time();
+            }
+        }
+    }
+
+    fn resume(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.paused += self.now() - paused_at;
+            if let Some(cpu) = self.cpu.as_mut() {
+                let now = cpu::thread_cpu_time();
+                if let (Some(now), Some(paused_at)) = (now, cpu.paused_at.take()) {
+                    cpu.paused += now.checked_sub(paused_at).unwrap_or_default();
+                }
+            }
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        if self.stopped {
+            self.elapsed_at_stop
+        } else {
+            self.active_time()
+        }
+    }
+
+    fn now(&self) -> Instant {
+        clock::now(self.clock.as_ref())
+    }
+
+    fn active_time(&self) -> Duration {
+        let now = self.now();
+        let paused = match self.paused_at {
+            Some(paused_at) => self.paused + (now - paused_at),
+            None => self.paused,
+        };
+        (now - self.start).checked_sub(paused).unwrap_or_default()
+    }
+
+    fn stop(&mut self) {
+        if self.stopped { return }
+        self.resume();
+        let elapsed = self.active_time();
         self.stopped = true;
-        let elapsed = self.start.elapsed();
-        let d = PrettyDuration::new(elapsed);
-        eprintln!("{}: {}", self.label, d);
+        self.elapsed_at_stop = elapsed;
+        // per-thread measurements are meaningless if the timer has moved
+        let same_thread = stack::current_thread_id() == self.thread;
+        let allocations = self.alloc.as_ref()
+            .filter(|_| same_thread)
+            .map(AllocScope::finish);
+        let cpu_time = self.cpu.as_ref()
+            .filter(|_| same_thread)
+            .and_then(CpuTimer::elapsed);
+        #[cfg(feature = "tracing")]
+        self.span.finish(elapsed);
+        watchdog::unregister(self.entry.id);
+        let (children, parent) = stack::pop(&self.entry);
+        if threshold::below_min(elapsed, self.min_duration) {
+            return;
+        }
+        let mut report = TimerReport::new(self.label.clone(), self.start, elapsed);
+        report.fields = mem::take(&mut self.fields);
+        report.paused = self.paused;
+        report.pauses = self.pauses;
+        report.cpu_time = cpu_time;
+        report.allocations = allocations;
+        report.warn_after = self.warn_after.or(report.warn_after);
+        report.location = self.location;
+        report.laps = mem::take(&mut self.laps);
+        report.children = children;
+        deliver(report, parent, self.sink.as_ref());
+    }
+}
+
+#[cfg(not(timing_off))]
+impl CpuTimer {
+    fn start() -> Option<CpuTimer> {
+        cpu::thread_cpu_time().map(|start| CpuTimer {
+            start,
+            paused_at: None,
+            paused: Duration::default(),
+        })
+    }
+
+    fn elapsed(&self) -> Option<Duration> {
+        let used = cpu::thread_cpu_time()?.checked_sub(self.start)?;
+        Some(used.checked_sub(self.paused).unwrap_or_default())
     }
 }
 
+/// Attaches a finished timer's report to the timer it was nested in, or
+/// if there isn't one, passes it to `sink` or the default sink.
+fn deliver(report: TimerReport, parent: Option<usize>, sink: Option<&Arc<dyn TimerSink>>) {
+    let report = match parent {
+        Some(parent) => match stack::attach(parent, report) {
+            Some(report) => report,
+            None => return,
+        },
+        None => report,
+    };
+    sink::record_to(sink, &report);
+}
+
+#[cfg(not(timing_off))]
+impl TimerBuilder {
+    pub fn new<S: Into<CowStr>>(label: S) -> Self {
+        TimerBuilder {
+            label: label.into(),
+            fields: Vec::new(),
+            sink: None,
+            clock: None,
+            cpu_time: false,
+            min_duration: None,
+            warn_after: None,
+            location: None,
+        }
+    }
+
+    /// Attaches a key-value pair to the timer. See `BlockTimer::with_field`.
+    pub fn field<K: Into<CowStr>, V: Into<Value>>(mut self, key: K, value: V) -> Self {
+        self.fields.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets the sink the timer reports to. See `BlockTimer::with_sink`.
+    pub fn sink<T: TimerSink + 'static>(mut self, sink: T) -> Self {
+        self.sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Sets the clock the timer reads the time from.
+    ///
+    /// If this is not set, the default from `clock::set_default_clock` is
+    /// used.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Also measures the CPU time used by the current thread while the
+    /// timer runs, where the platform supports it. This is reported
+    /// alongside the wall-clock time.
+    pub fn cpu_time(mut self) -> Self {
+        self.cpu_time = true;
+        self
+    }
+
+    /// Suppresses the timer's report if it finishes in less than `min`.
+    /// A nested timer below its minimum is left out of the outer timer's
+    /// report.
+    ///
+    /// If this is not set, the default from `set_default_min_duration`
+    /// is used.
+    pub fn min_duration(mut self, min: Duration) -> Self {
+        self.min_duration = Some(min);
+        self
+    }
+
+    /// Marks the timer's report as slow if it takes `limit` or longer.
+    ///
+    /// If this is not set, the default from `set_default_warn_after` is
+    /// used.
+    pub fn warn_after(mut self, limit: Duration) -> Self {
+        self.warn_after = Some(limit);
+        self
+    }
+
+    /// Records where in the source the timer was created. This is set by
+    /// the timing macros.
+    pub fn location(mut self, module_path: &'static str, file: &'static str,
+                    line: u32) -> Self {
+        self.location = Some(SourceLocation { module_path, file, line });
+        self
+    }
+
+    /// Starts the timer. If timing is switched off, or the timer is
+    /// excluded by the filter, this returns `BlockTimer::disabled()`.
+    pub fn start(self) -> BlockTimer {
+        let module_path = self.location.as_ref().map(|location| location.module_path);
+        if !toggle::label_enabled(&self.label, module_path) {
+            return BlockTimer::disabled();
+        }
+        let timer = alloc::untracked(|| self.start_running());
+        BlockTimer { running: Some(timer) }
+    }
+
+    fn start_running(self) -> Running {
+        epoch();
+        let clock = self.clock.or_else(clock::default_clock);
+        let cpu = if self.cpu_time { CpuTimer::start() } else { None };
+        #[cfg(feature = "tracing")]
+        let span = spans::TimerSpan::new(&self.label);
+        let mut timer = Running {
+            label: self.label,
+            fields: self.fields,
+            start: clock::now(clock.as_ref()),
+            stopped: false,
+            elapsed_at_stop: Duration::default(),
+            entry: stack::push(self.sink.clone()),
+            thread: stack::current_thread_id(),
+            paused_at: None,
+            paused: Duration::default(),
+            pauses: 0,
+            cpu,
+            alloc: None,
+            laps: Vec::new(),
+            sink: self.sink,
+            clock,
+            min_duration: self.min_duration,
+            warn_after: self.warn_after,
+            location: self.location,
+            #[cfg(feature = "tracing")]
+            span,
+        };
+        watchdog::register(timer.entry.id, &timer.label);
+        timer.alloc = AllocScope::start();
+        timer
+    }
+}
+
+#[cfg(not(timing_off))]
 impl Drop for BlockTimer {
     fn drop(&mut self) {
-        if self.stopped { return }
-        self.stop();
+        // the timer's state is freed here too, so that an outer timer
+        // doesn't count it
+        alloc::untracked(|| {
+            self.stop();
+            self.running = None;
+        });
     }
 }
 
+/// Calls `f`, returning its result and how long it took, according to the
+/// default clock.
+pub fn time<T, F: FnOnce() -> T>(f: F) -> (T, Duration) {
+    let clock = clock::default_clock();
+    let start = clock::now(clock.as_ref());
+    let result = f();
+    (result, clock::now(clock.as_ref()) - start)
+}
+
+/// Calls `f` inside a `BlockTimer` with the given label, returning its
+/// result and how long it took. The timer is reported as usual; if it is
+/// disabled, the duration is zero.
+pub fn time_labeled<S, T, F>(label: S, f: F) -> (T, Duration)
+    where S: Into<CowStr>,
+          F: FnOnce() -> T,
+{
+    let mut timer = BlockTimer::new(label);
+    let result = f();
+    timer.stop();
+    (result, timer.elapsed())
+}
+
 impl PrettyDuration {
     pub fn new(d: Duration) -> Self {
         let d = nanos_from_duration(d);
@@ -78,8 +548,240 @@ fn nanos_from_duration(d: Duration) -> u64 {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    #[cfg(not(timing_off))]
+    use clock::MockClock;
+    use sink::Collector;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn disabled() {
+        let mut timer = BlockTimer::disabled();
+        assert!(!timer.is_enabled());
+        assert_eq!(timer.lap("lap"), Duration::default());
+        timer.pause();
+        timer.resume();
+        timer.stop();
+        assert_eq!(timer.elapsed(), Duration::default());
+    }
+
+    #[test]
+    #[cfg(timing_off)]
+    fn compiled_out() {
+        assert_eq!(std::mem::size_of::<BlockTimer>(), 0);
+        assert_eq!(std::mem::size_of::<TimerBuilder>(), 0);
+        let collector = Arc::new(Collector::new());
+        let mut timer = BlockTimer::builder("off").sink(collector.clone()).start();
+        assert!(!timer.is_enabled());
+        timer.stop();
+        assert_eq!(timer.elapsed(), Duration::default());
+
+        let mut formatted = false;
+        {
+            time_block!("{}", { formatted = true; 1 });
+        }
+        assert!(!formatted);
+        assert_eq!(time_expr!(1 + 2), 3);
+        assert_eq!(time_expr!("four", 4), 4);
+        assert!(collector.take().is_empty());
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn nested_timers() {
+        let collector = Arc::new(Collector::new());
+        {
+            let _outer = BlockTimer::with_sink("outer", collector.clone());
+            for _ in 0..2 {
+                let _inner = BlockTimer::new("inner");
+                BlockTimer::new("leaf");
+            }
+        }
+        let reports = collector.take();
+        assert_eq!(reports.len(), 1);
+        let outer = &reports[0];
+        assert_eq!(outer.label, "outer");
+        assert_eq!(outer.children.len(), 2);
+        assert_eq!(outer.children[0].label, "inner");
+        assert_eq!(outer.children[0].children[0].label, "leaf");
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn stopped_out_of_order() {
+        let collector = Arc::new(Collector::new());
+        let mut outer = BlockTimer::with_sink("outer", collector.clone());
+        let inner = BlockTimer::with_sink("inner", collector.clone());
+        outer.stop();
+        drop(inner);
+        let labels = collector.take().into_iter()
+            .map(|r| r.label)
+            .collect::<Vec<_>>();
+        assert_eq!(labels, vec!["outer", "inner"]);
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn laps() {
+        let collector = Arc::new(Collector::new());
+        let mut timer = BlockTimer::with_sink("phases", collector.clone());
+        let first = timer.lap("one");
+        let second = timer.lap("two");
+        timer.stop();
+        let report = &collector.take()[0];
+        assert_eq!(report.laps.len(), 2);
+        assert_eq!(report.laps[0].label, "one");
+        assert_eq!(report.laps[0].elapsed, first);
+        assert_eq!(report.laps[1].elapsed, second);
+        assert_eq!(report.laps[1].cumulative, first + second);
+        assert!(report.elapsed >= report.laps[1].cumulative);
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn many_nested_timers() {
+        let collector = Arc::new(Collector::new());
+        {
+            let _main = BlockTimer::with_sink("main", collector.clone());
+            for _ in 0..MAX_CHILDREN + 500 {
+                BlockTimer::new("parse");
+            }
+        }
+        let main = &collector.take()[0];
+        assert_eq!(main.children.len(), MAX_CHILDREN);
+        assert!(main.children.iter().all(|child| child.label == "parse"));
+        assert_eq!(main.children[MAX_CHILDREN - 1].count, 501);
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn finished_on_another_thread() {
+        let collector = Arc::new(Collector::new());
+        let outer = BlockTimer::with_sink("outer", collector.clone());
+        BlockTimer::new("inner");
+        std::thread::spawn(move || drop(outer)).join().unwrap();
+        // the next timer on this thread finds `outer` gone, and records
+        // what finished inside it
+        BlockTimer::with_sink("next", collector.clone());
+        let reports = collector.take();
+        let labels = reports.iter().map(|r| r.label.as_ref()).collect::<Vec<_>>();
+        assert_eq!(labels, vec!["outer", "inner", "next"]);
+        assert!(reports[0].children.is_empty());
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn lap_after_stop() {
+        let clock = Arc::new(MockClock::new());
+        let mut timer = BlockTimer::builder("stopped")
+            .sink(Collector::new())
+            .clock(clock.clone())
+            .start();
+        clock.advance(Duration::from_millis(1));
+        timer.stop();
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(timer.lap("late"), Duration::default());
+        assert_eq!(timer.elapsed(), Duration::from_millis(1));
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn pause_and_resume() {
+        let collector = Arc::new(Collector::new());
+        let clock = Arc::new(MockClock::new());
+        let ms = Duration::from_millis;
+        let mut timer = BlockTimer::builder("paused")
+            .sink(collector.clone())
+            .clock(clock.clone())
+            .start();
+        clock.advance(ms(1));
+        timer.pause();
+        clock.advance(ms(20));
+        timer.resume();
+        assert_eq!(timer.lap("lap").as_millis(), 1);
+        clock.advance(ms(2));
+        timer.pause();
+        clock.advance(ms(5));
+        timer.stop();
+        let report = &collector.take()[0];
+        assert_eq!(report.pauses, 2);
+        assert_eq!(report.paused, ms(25));
+        assert_eq!(report.elapsed, ms(3));
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn elapsed() {
+        let collector = Arc::new(Collector::new());
+        let mut timer = BlockTimer::with_sink("elapsed", collector.clone());
+        let before = timer.elapsed();
+        timer.stop();
+        let after = timer.elapsed();
+        assert!(after >= before);
+        assert_eq!(timer.elapsed(), after);
+        assert_eq!(collector.take()[0].elapsed, after);
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn time_closures() {
+        let (value, elapsed) = time(|| {
+            ::std::thread::sleep(Duration::from_millis(5));
+            42
+        });
+        assert_eq!(value, 42);
+        assert!(elapsed >= Duration::from_millis(5));
+
+        let collector = Arc::new(Collector::new());
+        let outer = BlockTimer::with_sink("outer", collector.clone());
+        let (value, elapsed) = time_labeled("labeled", || "done");
+        assert_eq!(value, "done");
+        drop(outer);
+        let inner = &collector.take()[0].children[0];
+        assert_eq!(inner.label, "labeled");
+        assert_eq!(inner.elapsed, elapsed);
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", not(timing_off)))]
+    fn cpu_time() {
+        let collector = Arc::new(Collector::new());
+        let mut timer = BlockTimer::builder("cpu")
+            .sink(collector.clone())
+            .cpu_time()
+            .start();
+        ::std::thread::sleep(Duration::from_millis(20));
+        timer.stop();
+        let report = &collector.take()[0];
+        let cpu_time = report.cpu_time.unwrap();
+        assert!(cpu_time < report.elapsed);
+        BlockTimer::with_sink("no cpu", collector.clone());
+        assert!(collector.take()[0].cpu_time.is_none());
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn thresholds() {
+        let collector = Arc::new(Collector::new());
+        BlockTimer::builder("fast")
+            .sink(collector.clone())
+            .min_duration(Duration::from_secs(60))
+            .start();
+        {
+            let _outer = BlockTimer::builder("outer")
+                .sink(collector.clone())
+                .warn_after(Duration::default())
+                .start();
+            BlockTimer::builder("hidden").min_duration(Duration::from_secs(60)).start();
+        }
+        let reports = collector.take();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].label, "outer");
+        assert!(reports[0].is_slow());
+        assert!(reports[0].children.is_empty());
+    }
 }
@@ -0,0 +1,197 @@
+//! Macros for creating timers labelled from their source location.
+
+use std::any;
+use std::borrow::Cow;
+use std::fmt;
+
+use CowStr;
+
+/// Starts a timer that runs until the end of the enclosing block.
+///
+/// With no arguments, the timer is labelled with the path of the enclosing
+/// function; otherwise the arguments are used as with `format!`. In either
+/// case the timer records where it was created. If timing is switched off,
+/// the label is not formatted.
+///
+/// Fields can be attached to the timer by listing them after a `;`, as in
+/// `time_block!("parse {}", n; file = path, bytes = len)`; see
+/// `BlockTimer::with_field`.
+///
+/// ```
+/// # #[macro_use] extern crate test_helpers;
+/// fn parse(input: &str) {
+///     time_block!();
+///     // ...
+///     for line in input.lines() {
+///         time_block!("line {}", line; len = line.len());
+///         // ...
+///     }
+/// }
+/// # fn main() { parse("a\nb") }
+/// ```
+#[macro_export]
+macro_rules! time_block {
+    (; $($key:ident = $value:expr),+ $(,)*) => {
+        let _block_timer = if $crate::timing_enabled() {
+            $crate::BlockTimer::builder($crate::__function_path!())
+                .location(module_path!(), file!(), line!())
+                $(.field(stringify!($key), $value))+
+                .start()
+        } else {
+            $crate::BlockTimer::disabled()
+        };
+    };
+    ($fmt:expr $(, $arg:expr)* ; $($key:ident = $value:expr),+ $(,)*) => {
+        let _block_timer = if $crate::timing_enabled() {
+            $crate::BlockTimer::builder($crate::__label(format_args!($fmt $(, $arg)*)))
+                .location(module_path!(), file!(), line!())
+                $(.field(stringify!($key), $value))+
+                .start()
+        } else {
+            $crate::BlockTimer::disabled()
+        };
+    };
+    () => {
+        let _block_timer = if $crate::timing_enabled() {
+            $crate::BlockTimer::builder($crate::__function_path!())
+                .location(module_path!(), file!(), line!())
+                .start()
+        } else {
+            $crate::BlockTimer::disabled()
+        };
+    };
+    ($($fmt:tt)+) => {
+        let _block_timer = if $crate::timing_enabled() {
+            $crate::BlockTimer::builder($crate::__label(format_args!($($fmt)+)))
+                .location(module_path!(), file!(), line!())
+                .start()
+        } else {
+            $crate::BlockTimer::disabled()
+        };
+    };
+}
+
+/// Times the evaluation of an expression, returning its value.
+///
+/// The timer is labelled with the text of the expression, unless a label
+/// is passed before it.
+///
+/// ```
+/// # #[macro_use] extern crate test_helpers;
+/// # fn main() {
+/// let sum = time_expr!((0..100u32).sum::<u32>());
+/// let product = time_expr!("product", (1..10u32).product::<u32>());
+/// # assert_eq!(sum, 4950);
+/// # let _ = product;
+/// # }
+/// ```
+#[macro_export]
+macro_rules! time_expr {
+    ($e:expr) => {
+        $crate::time_expr!(stringify!($e), $e)
+    };
+    ($label:expr, $e:expr) => {{
+        let _block_timer = if $crate::timing_enabled() {
+            $crate::BlockTimer::builder($label)
+                .location(module_path!(), file!(), line!())
+                .start()
+        } else {
+            $crate::BlockTimer::disabled()
+        };
+        $e
+    }};
+}
+
+/// Expands to the path of the enclosing function.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __function_path {
+    () => {{
+        fn __f() {}
+        $crate::__function_path($crate::__type_name_of(__f))
+    }};
+}
+
+#[doc(hidden)]
+pub fn __type_name_of<T>(_: T) -> &'static str {
+    any::type_name::<T>()
+}
+
+/// Trims the helper function, and any enclosing closures, from the type
+/// name produced by `__function_path!`.
+#[doc(hidden)]
+pub fn __function_path(name: &'static str) -> &'static str {
+    let mut name = name.trim_end_matches("::__f");
+    while name.ends_with("::{{closure}}") {
+        name = &name[..name.len() - "::{{closure}}".len()];
+    }
+    name
+}
+
+/// Avoids allocating a label when the format string has no arguments.
+#[doc(hidden)]
+pub fn __label(args: fmt::Arguments) -> CowStr {
+    match args.as_str() {
+        Some(s) => Cow::Borrowed(s),
+        None => Cow::Owned(fmt::format(args)),
+    }
+}
+
+#[cfg(all(test, not(timing_off)))]
+mod tests {
+    use std::sync::Arc;
+    use sink::Collector;
+    use {BlockTimer, Value};
+
+    #[test]
+    fn labels() {
+        let collector = Arc::new(Collector::new());
+        let value = {
+            let _outer = BlockTimer::with_sink("outer", collector.clone());
+            time_block!();
+            for i in 0..2 {
+                time_block!("iteration {}", i);
+            }
+            {
+                time_block!("fields"; index = 2, name = "two",);
+            }
+            {
+                time_block!(; answer = 42);
+            }
+            let closure = || { time_block!(); };
+            closure();
+            time_expr!(1 + 2) + time_expr!("four", 4)
+        };
+        assert_eq!(value, 7);
+        let report = &collector.take()[0];
+        let labels = report.children[0].children.iter()
+            .map(|r| r.label.as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(report.children[0].label, "test_helpers::macros::tests::labels");
+        assert_eq!(labels, vec!["iteration 0", "iteration 1", "fields",
+                                "test_helpers::macros::tests::labels",
+                                "test_helpers::macros::tests::labels", "1 + 2", "four"]);
+        let fields = &report.children[0].children[2].fields;
+        assert_eq!(*fields, vec![("index".into(), Value::Int(2)), ("name".into(), "two".into())]);
+        assert_eq!(report.children[0].children[3].fields, vec![("answer".into(), Value::Int(42))]);
+        let location = report.children[0].location.as_ref().unwrap();
+        assert_eq!(location.module_path, "test_helpers::macros::tests");
+        assert_eq!(location.file, file!());
+    }
+
+    #[test]
+    fn borrowed_fields() {
+        let collector = Arc::new(Collector::new());
+        let path = format!("src/{}.rs", "main");
+        {
+            let _outer = BlockTimer::with_sink("outer", collector.clone())
+                .with_field("file", path.as_str());
+            let file = path.as_str();
+            time_block!("read"; file = file);
+        }
+        let report = &collector.take()[0];
+        let file = Value::Str("src/main.rs".into());
+        assert_eq!(report.fields, vec![("file".into(), file.clone())]);
+        assert_eq!(report.children[0].fields, vec![("file".into(), file)]);
+    }
+}
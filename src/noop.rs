@@ -0,0 +1,125 @@
+//! Empty versions of `BlockTimer` and `TimerBuilder`, used in place of the
+//! real ones when timers are compiled out with the `timing-off` or
+//! `release-timing-off` features.
+
+use std::time::Duration;
+
+use clock::Clock;
+use sink::TimerSink;
+use {CowStr, Value};
+
+/// A timer that has been compiled out. Every method does nothing.
+pub struct BlockTimer {
+    _private: (),
+}
+
+/// Configures a `BlockTimer` that has been compiled out. Every method does
+/// nothing.
+pub struct TimerBuilder {
+    _private: (),
+}
+
+impl BlockTimer {
+    #[inline]
+    pub fn new<S: Into<CowStr>>(_label: S) -> Self {
+        BlockTimer::disabled()
+    }
+
+    #[inline]
+    pub fn with_sink<S, T>(_label: S, _sink: T) -> Self
+        where S: Into<CowStr>,
+              T: TimerSink + 'static,
+    {
+        BlockTimer::disabled()
+    }
+
+    #[inline]
+    pub fn builder<S: Into<CowStr>>(_label: S) -> TimerBuilder {
+        TimerBuilder { _private: () }
+    }
+
+    #[inline]
+    pub fn disabled() -> Self {
+        BlockTimer { _private: () }
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    pub fn with_field<K, V>(self, _key: K, _value: V) -> Self
+        where K: Into<CowStr>,
+              V: Into<Value>,
+    {
+        self
+    }
+
+    #[inline]
+    pub fn lap<S: Into<CowStr>>(&mut self, _label: S) -> Duration {
+        Duration::default()
+    }
+
+    #[inline]
+    pub fn pause(&mut self) {}
+
+    #[inline]
+    pub fn resume(&mut self) {}
+
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        Duration::default()
+    }
+
+    #[inline]
+    pub fn stop(&mut self) {}
+}
+
+impl TimerBuilder {
+    #[inline]
+    pub fn new<S: Into<CowStr>>(_label: S) -> Self {
+        TimerBuilder { _private: () }
+    }
+
+    #[inline]
+    pub fn field<K: Into<CowStr>, V: Into<Value>>(self, _key: K, _value: V) -> Self {
+        self
+    }
+
+    #[inline]
+    pub fn sink<T: TimerSink + 'static>(self, _sink: T) -> Self {
+        self
+    }
+
+    #[inline]
+    pub fn clock<C: Clock + 'static>(self, _clock: C) -> Self {
+        self
+    }
+
+    #[inline]
+    pub fn cpu_time(self) -> Self {
+        self
+    }
+
+    #[inline]
+    pub fn min_duration(self, _min: Duration) -> Self {
+        self
+    }
+
+    #[inline]
+    pub fn warn_after(self, _limit: Duration) -> Self {
+        self
+    }
+
+    #[inline]
+    pub fn location(self, _module_path: &'static str, _file: &'static str,
+                    _line: u32) -> Self {
+        self
+    }
+
+    #[inline]
+    pub fn start(self) -> BlockTimer {
+        BlockTimer::disabled()
+    }
+}
@@ -0,0 +1,308 @@
+//! Aggregated statistics for timers that run many times.
+//!
+//! A `Registry` is a `TimerSink` that, instead of printing each report,
+//! accumulates statistics for each label; these can be printed as a table
+//! or inspected at any point. The global registry can be installed as the
+//! default sink with `enable`.
+//!
+//! Timings can also be grouped by the values of some of their fields, with
+//! `Registry::set_group_by`.
+
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Mutex, Once, RwLock};
+use std::time::Duration;
+
+use exit;
+use histogram::Histogram;
+use report::{Fields, TimerReport};
+use sink;
+use PrettyDuration;
+
+static GLOBAL: Registry = Registry::new();
+
+/// Accumulates statistics for timers, keyed by label.
+#[derive(Debug, Default)]
+pub struct Registry {
+    stats: Mutex<BTreeMap<String, Stats>>,
+    group_by: RwLock<Vec<String>>,
+}
+
+/// Statistics for the timings recorded under one label.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub count: u64,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    /// The distribution of timings, for percentiles.
+    pub histogram: Histogram,
+    // running mean and sum of squared differences, in nanoseconds
+    mean: f64,
+    m2: f64,
+}
+
+/// A snapshot of a registry's statistics, sorted by descending total time.
+///
+/// The `Display` impl renders this as a table.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub entries: Vec<(String, Stats)>,
+}
+
+/// Returns the global registry.
+pub fn global() -> &'static Registry {
+    &GLOBAL
+}
+
+/// Makes the global registry the default sink, so that timers without a
+/// sink of their own are aggregated instead of printed.
+pub fn enable() {
+    sink::set_default_sink(global());
+}
+
+/// Arranges for the global registry's summary to be printed to stderr when
+/// the process exits normally. Calling this more than once has no effect.
+pub fn print_summary_at_exit() {
+    extern "C" fn print_global_summary() {
+        global().print_summary();
+    }
+
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| exit::at_exit(print_global_summary));
+}
+
+impl Registry {
+    pub const fn new() -> Self {
+        Registry {
+            stats: Mutex::new(BTreeMap::new()),
+            group_by: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Records timers separately for each combination of values of the
+    /// given fields. Timers are keyed by their label followed by those of
+    /// the fields they have, as in `parse{file=foo.rs}`.
+    ///
+    /// This affects only timings recorded afterwards.
+    pub fn set_group_by<I, S>(&self, keys: I)
+        where I: IntoIterator<Item = S>,
+              S: Into<String>,
+    {
+        *self.group_by.write().unwrap() = keys.into_iter().map(Into::into).collect();
+    }
+
+    /// Adds a single timing to the statistics for `label`.
+    pub fn record_duration(&self, label: &str, elapsed: Duration) {
+        let mut stats = self.stats.lock().unwrap();
+        match stats.get_mut(label) {
+            Some(stats) => stats.add(elapsed),
+            None => { stats.insert(label.to_owned(), Stats::new(elapsed)); }
+        }
+    }
+
+    /// Returns the statistics for `label`, if any timings were recorded.
+    pub fn get(&self, label: &str) -> Option<Stats> {
+        self.stats.lock().unwrap().get(label).cloned()
+    }
+
+    /// Returns a snapshot of the current statistics.
+    pub fn summary(&self) -> Summary {
+        let mut entries = self.stats.lock().unwrap().iter()
+            .map(|(label, stats)| (label.clone(), stats.clone()))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(_, stats)| Reverse(stats.total));
+        Summary { entries }
+    }
+
+    /// Prints the current summary to stderr.
+    pub fn print_summary(&self) {
+        eprintln!("{}", self.summary());
+    }
+
+    /// Prints a histogram of the timings for `label` to stderr.
+    pub fn print_histogram(&self, label: &str) {
+        match self.get(label) {
+            Some(stats) => eprintln!("{}:\n{}", label, stats.histogram),
+            None => eprintln!("{}: no timings", label),
+        }
+    }
+
+    /// Discards all recorded statistics.
+    pub fn clear(&self) {
+        self.stats.lock().unwrap().clear();
+    }
+
+    fn record_tree(&self, report: &TimerReport) {
+        self.record_duration(&self.key(report), report.elapsed);
+        for child in &report.children {
+            self.record_tree(child);
+        }
+    }
+
+    /// Returns the label under which `report` is recorded.
+    fn key<'a>(&self, report: &'a TimerReport) -> Cow<'a, str> {
+        let group_by = self.group_by.read().unwrap();
+        let fields = report.fields.iter()
+            .filter(|(key, _)| group_by.iter().any(|k| k == key))
+            .cloned()
+            .collect::<Vec<_>>();
+        if fields.is_empty() {
+            Cow::Borrowed(&report.label)
+        } else {
+            Cow::Owned(format!("{}{}", report.label, Fields(&fields)))
+        }
+    }
+}
+
+impl sink::TimerSink for Registry {
+    fn record(&self, report: &TimerReport) {
+        self.record_tree(report);
+    }
+
+    fn aggregates(&self) -> bool {
+        true
+    }
+}
+
+impl Stats {
+    fn new(elapsed: Duration) -> Self {
+        let mut histogram = Histogram::new();
+        histogram.record(elapsed);
+        Stats {
+            count: 1,
+            total: elapsed,
+            min: elapsed,
+            max: elapsed,
+            histogram,
+            mean: elapsed.as_nanos() as f64,
+            m2: 0.0,
+        }
+    }
+
+    fn add(&mut self, elapsed: Duration) {
+        self.count += 1;
+        self.total += elapsed;
+        self.min = self.min.min(elapsed);
+        self.max = self.max.max(elapsed);
+        self.histogram.record(elapsed);
+        // Welford's online algorithm
+        let nanos = elapsed.as_nanos() as f64;
+        let delta = nanos - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (nanos - self.mean);
+    }
+
+    pub fn mean(&self) -> Duration {
+        Duration::from_nanos((self.total.as_nanos() / self.count as u128) as u64)
+    }
+
+    /// The sample standard deviation.
+    pub fn std_dev(&self) -> Duration {
+        if self.count < 2 {
+            return Duration::default();
+        }
+        let variance = self.m2 / (self.count - 1) as f64;
+        Duration::from_nanos(variance.sqrt() as u64)
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.entries.iter()
+            .map(|(label, _)| label.chars().count())
+            .chain(Some("label".len()))
+            .max()
+            .unwrap();
+        write!(f, "{:<width$} {:>8} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+               "label", "count", "total", "mean", "min", "p50", "p99", "max", "std dev",
+               width = width)?;
+        for (label, stats) in &self.entries {
+            let pretty = |d| PrettyDuration::new(d).to_string();
+            write!(f, "\n{:<width$} {:>8} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+                   label, stats.count, pretty(stats.total), pretty(stats.mean()),
+                   pretty(stats.min), pretty(stats.histogram.p50()),
+                   pretty(stats.histogram.p99()), pretty(stats.max),
+                   pretty(stats.std_dev()), width = width)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats() {
+        let registry = Registry::new();
+        for millis in &[2, 4, 4, 4, 5, 5, 7, 9] {
+            registry.record_duration("parse", Duration::from_millis(*millis));
+        }
+        let stats = registry.get("parse").unwrap();
+        assert_eq!(stats.count, 8);
+        assert_eq!(stats.total, Duration::from_millis(40));
+        assert_eq!(stats.min, Duration::from_millis(2));
+        assert_eq!(stats.max, Duration::from_millis(9));
+        assert_eq!(stats.mean(), Duration::from_millis(5));
+        // sample std dev of the above is ~2.138
+        assert_eq!(stats.std_dev().as_micros(), 2138);
+        assert_eq!(stats.histogram.count(), 8);
+        assert_eq!(stats.histogram.max(), Duration::from_millis(9));
+        assert!(registry.get("render").is_none());
+    }
+
+    #[test]
+    fn summary_sorted_by_total() {
+        let registry = Registry::new();
+        registry.record_duration("fast", Duration::from_millis(1));
+        registry.record_duration("slow", Duration::from_millis(8));
+        registry.record_duration("fast", Duration::from_millis(1));
+        let summary = registry.summary();
+        let labels = summary.entries.iter().map(|e| e.0.as_str()).collect::<Vec<_>>();
+        assert_eq!(labels, vec!["slow", "fast"]);
+        let expected = "\
+label    count     total      mean       min       p50       p99       max   std dev
+slow         1     8.0ms     8.0ms     8.0ms     8.0ms     8.0ms     8.0ms       0ns
+fast         2     2.0ms     1.0ms     1.0ms     1.0ms     1.0ms     1.0ms       0ns";
+        assert_eq!(summary.to_string(), expected);
+    }
+
+    #[test]
+    fn group_by_fields() {
+        use sink::TimerSink;
+        use report::tests::report;
+
+        let registry = Registry::new();
+        registry.set_group_by(vec!["file"]);
+        let mut parse = report("parse", 2, vec![report("lex", 1, vec![])]);
+        parse.fields = vec![("file".into(), "foo.rs".into()), ("bytes".into(), 12040.into())];
+        registry.record(&parse);
+        parse.fields[0].1 = "bar.rs".into();
+        registry.record(&parse);
+        assert_eq!(registry.get("parse{file=foo.rs}").unwrap().count, 1);
+        assert_eq!(registry.get("parse{file=bar.rs}").unwrap().count, 1);
+        assert_eq!(registry.get("lex").unwrap().count, 2);
+    }
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn nested_timers_recorded_as_they_finish() {
+        use std::sync::Arc;
+        use BlockTimer;
+
+        let registry = Arc::new(Registry::new());
+        {
+            let _main = BlockTimer::with_sink("main", registry.clone());
+            for _ in 0..3 {
+                BlockTimer::new("parse");
+            }
+            assert_eq!(registry.get("parse").unwrap().count, 3);
+            assert!(registry.get("main").is_none());
+        }
+        assert_eq!(registry.get("parse").unwrap().count, 3);
+        assert_eq!(registry.get("main").unwrap().count, 1);
+    }
+}
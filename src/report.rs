@@ -0,0 +1,430 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use alloc::AllocStats;
+use event::TimingEvent;
+use value::Value;
+use super::{epoch, stack, threshold, CowStr, PrettyDuration};
+
+/// The result of a completed `BlockTimer`, as passed to a `TimerSink`.
+///
+/// Timers that finish while another timer is running on the same thread
+/// are included in that timer's report, as its `children`; only the
+/// outermost timer's report is passed to a sink. When displayed, nested
+/// reports are shown as an indented tree.
+///
+/// A report keeps at most `MAX_CHILDREN` children. The reports of any
+/// further nested timers are merged into an earlier child with the same
+/// label, or into a child labelled `(other)`; see `count`. Sinks that
+/// aggregate timings can avoid this with `TimerSink::aggregates`.
+#[derive(Debug, Clone)]
+pub struct TimerReport {
+    pub label: CowStr,
+    /// The number of timers this report covers. This is more than one if
+    /// the reports of several nested timers were merged, in which case the
+    /// times and counts are totals, and the fields, laps and children are
+    /// those of the first timer.
+    pub count: u32,
+    /// Key-value metadata, attached with `BlockTimer::with_field`.
+    pub fields: Vec<(CowStr, Value)>,
+    /// When the timer started, relative to the first timer started in this
+    /// process.
+    pub start_offset: Duration,
+    /// The thread the timer ran on.
+    pub thread: ThreadInfo,
+    /// The time the timer was running, excluding any time it was paused.
+    pub elapsed: Duration,
+    /// The total time the timer was paused.
+    pub paused: Duration,
+    /// The number of times the timer was paused.
+    pub pauses: u32,
+    /// The CPU time used by the timer's thread while it was running, if
+    /// this was requested with `TimerBuilder::cpu_time`.
+    pub cpu_time: Option<Duration>,
+    /// The allocations made on the timer's thread while it was running, if
+    /// a `CountingAllocator` is installed.
+    pub allocations: Option<AllocStats>,
+    /// For a timed future, how often it was polled.
+    pub polls: Option<Polls>,
+    /// The duration above which this timer is considered slow, if any.
+    pub warn_after: Option<Duration>,
+    /// Where the timer was created, if it was created with a macro.
+    pub location: Option<SourceLocation>,
+    pub laps: Vec<Lap>,
+    pub children: Vec<TimerReport>,
+}
+
+/// The most children a report keeps. See `TimerReport`.
+pub const MAX_CHILDREN: usize = 1000;
+
+const OTHER_LABEL: &str = "(other)";
+
+/// An intermediate checkpoint recorded with `BlockTimer::lap`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lap {
+    pub label: CowStr,
+    /// The time since the previous lap, or since the timer started.
+    pub elapsed: Duration,
+    /// The time since the timer started.
+    pub cumulative: Duration,
+}
+
+/// How a timed future was polled. See `Timed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Polls {
+    /// The number of times the future was polled.
+    pub count: u32,
+    /// The total time spent inside the future's `poll` method.
+    pub busy: Duration,
+}
+
+/// The place in the source where a timer was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    pub module_path: &'static str,
+    pub file: &'static str,
+    pub line: u32,
+}
+
+/// Identifies a thread that timers ran on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ThreadInfo {
+    /// A number assigned to each thread, in the order that threads first
+    /// use a timer.
+    pub id: u64,
+    pub name: Option<Arc<str>>,
+}
+
+/// Adds `child` to `children`. Once there are `MAX_CHILDREN`, it is merged
+/// into the last child with the same label instead, or failing that, into
+/// an `(other)` child.
+pub(crate) fn push_child(children: &mut Vec<TimerReport>, mut child: TimerReport) {
+    if children.len() < MAX_CHILDREN {
+        children.push(child);
+        return;
+    }
+    let existing = children.iter().rposition(|c| c.label == child.label)
+        .or_else(|| children.iter().rposition(|c| c.label == OTHER_LABEL));
+    match existing {
+        Some(i) => children[i].merge(&child),
+        None => {
+            child.label = Cow::Borrowed(OTHER_LABEL);
+            child.fields.clear();
+            child.location = None;
+            child.laps.clear();
+            child.children.clear();
+            children.push(child);
+        }
+    }
+}
+
+/// Displays a report, highlighting slow timers with terminal colors.
+pub(crate) struct Colored<'a>(pub(crate) &'a TimerReport);
+
+impl TimerReport {
+    /// Creates a report for a timer that started at `start` and ran for
+    /// `elapsed` on the current thread, with the default `warn_after` limit
+    /// and nothing else recorded.
+    pub(crate) fn new(label: CowStr, start: Instant, elapsed: Duration) -> TimerReport {
+        TimerReport {
+            label,
+            count: 1,
+            fields: Vec::new(),
+            start_offset: start.saturating_duration_since(epoch()),
+            thread: stack::current_thread(),
+            elapsed,
+            paused: Duration::default(),
+            pauses: 0,
+            cpu_time: None,
+            allocations: None,
+            polls: None,
+            warn_after: threshold::default_warn_after(),
+            location: None,
+            laps: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Returns `true` if this timer took at least as long as its
+    /// `warn_after` limit, on average if it covers several timers.
+    pub fn is_slow(&self) -> bool {
+        self.warn_after.map(|limit| self.elapsed / self.count.max(1) >= limit).unwrap_or(false)
+    }
+
+    /// Adds the times and counts of `other` to this report's, as if they
+    /// were one timer.
+    fn merge(&mut self, other: &TimerReport) {
+        self.count = self.count.saturating_add(other.count);
+        self.start_offset = self.start_offset.min(other.start_offset);
+        self.elapsed += other.elapsed;
+        self.paused += other.paused;
+        self.pauses = self.pauses.saturating_add(other.pauses);
+        self.cpu_time = match (self.cpu_time, other.cpu_time) {
+            (Some(a), Some(b)) => Some(a + b),
+            _ => None,
+        };
+        self.allocations = match (self.allocations, other.allocations) {
+            (Some(a), Some(b)) => Some(AllocStats {
+                count: a.count + b.count,
+                bytes_allocated: a.bytes_allocated + b.bytes_allocated,
+                bytes_freed: a.bytes_freed + b.bytes_freed,
+                peak: a.peak.max(b.peak),
+            }),
+            _ => None,
+        };
+        self.polls = match (self.polls, other.polls) {
+            (Some(a), Some(b)) => Some(Polls {
+                count: a.count.saturating_add(b.count),
+                busy: a.busy + b.busy,
+            }),
+            _ => None,
+        };
+    }
+
+    /// Returns a structured event for this timer and for each nested timer,
+    /// in the order that they started.
+    pub fn events(&self) -> Vec<TimingEvent> {
+        let mut events = Vec::new();
+        self.push_events(0, &mut events);
+        events
+    }
+
+    fn push_events(&self, depth: usize, events: &mut Vec<TimingEvent>) {
+        events.push(TimingEvent::from_report(self, depth));
+        for child in &self.children {
+            child.push_events(depth + 1, events);
+        }
+    }
+
+    /// Returns `true` if this timer or any nested timer is slow.
+    pub fn any_slow(&self) -> bool {
+        self.is_slow() || self.children.iter().any(TimerReport::any_slow)
+    }
+
+    /// The time not accounted for by any of this report's children.
+    pub fn self_time(&self) -> Duration {
+        let children = self.children.iter().map(|c| c.elapsed).sum();
+        self.elapsed.checked_sub(children).unwrap_or_default()
+    }
+
+    fn fmt_tree(&self, f: &mut fmt::Formatter, depth: usize,
+                parent: Option<Duration>, color: bool) -> fmt::Result {
+        let slow = self.is_slow();
+        if slow && color {
+            f.write_str("\x1b[1;33m")?;
+        }
+        write!(f, "{:indent$}{}{}: {}", "", self.label, Fields(&self.fields),
+               PrettyDuration::new(self.elapsed), indent = depth * 2)?;
+        if let Some(cpu_time) = self.cpu_time {
+            write!(f, " wall / {} cpu", PrettyDuration::new(cpu_time))?;
+        }
+        if let Some(parent) = parent {
+            write!(f, " ({:.1}%)", percent(self.elapsed, parent))?;
+        }
+        if self.count > 1 {
+            write!(f, " [{} timers]", self.count)?;
+        }
+        if self.pauses > 0 {
+            let plural = if self.pauses == 1 { "" } else { "s" };
+            write!(f, " [paused {}, {} pause{}]", PrettyDuration::new(self.paused),
+                   self.pauses, plural)?;
+        }
+        if let Some(allocations) = self.allocations {
+            write!(f, " [{}]", allocations)?;
+        }
+        if let Some(polls) = self.polls {
+            let plural = if polls.count == 1 { "" } else { "s" };
+            write!(f, " [busy {}, {} poll{}]", PrettyDuration::new(polls.busy),
+                   polls.count, plural)?;
+        }
+        if let Some(limit) = self.warn_after.filter(|_| slow) {
+            write!(f, " [SLOW >= {}]", PrettyDuration::new(limit))?;
+        }
+        if slow && color {
+            f.write_str("\x1b[0m")?;
+        }
+        for lap in &self.laps {
+            write!(f, "\n{:indent$}- {}: {} (at {})", "", lap.label,
+                   PrettyDuration::new(lap.elapsed), PrettyDuration::new(lap.cumulative),
+                   indent = (depth + 1) * 2)?;
+        }
+        if self.children.is_empty() {
+            return Ok(());
+        }
+        for child in &self.children {
+            writeln!(f)?;
+            child.fmt_tree(f, depth + 1, Some(self.elapsed), color)?;
+        }
+        let self_time = self.self_time();
+        write!(f, "\n{:indent$}(self): {} ({:.1}%)", "",
+               PrettyDuration::new(self_time), percent(self_time, self.elapsed),
+               indent = (depth + 1) * 2)
+    }
+}
+
+impl fmt::Display for TimerReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_tree(f, 0, None, false)
+    }
+}
+
+impl<'a> fmt::Display for Colored<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_tree(f, 0, None, true)
+    }
+}
+
+/// Displays a timer's fields as `{key=value, ...}`, or nothing if there
+/// are none.
+pub(crate) struct Fields<'a>(pub(crate) &'a [(CowStr, Value)]);
+
+impl<'a> fmt::Display for Fields<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        f.write_str("{")?;
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}={}", key, value)?;
+        }
+        f.write_str("}")
+    }
+}
+
+fn percent(part: Duration, whole: Duration) -> f64 {
+    if whole == Duration::default() {
+        return 0.0;
+    }
+    part.as_secs_f64() / whole.as_secs_f64() * 100.0
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A report with only a label, duration and children, for tests.
+    pub(crate) fn report(label: &'static str, millis: u64,
+                         children: Vec<TimerReport>) -> TimerReport {
+        TimerReport {
+            label: label.into(),
+            count: 1,
+            fields: Vec::new(),
+            start_offset: Duration::default(),
+            thread: ThreadInfo { id: 1, name: None },
+            elapsed: Duration::from_millis(millis),
+            paused: Duration::default(),
+            pauses: 0,
+            cpu_time: None,
+            allocations: None,
+            polls: None,
+            warn_after: None,
+            location: None,
+            laps: Vec::new(),
+            children,
+        }
+    }
+
+    fn lap(label: &'static str, millis: u64, cumulative: u64) -> Lap {
+        Lap {
+            label: label.into(),
+            elapsed: Duration::from_millis(millis),
+            cumulative: Duration::from_millis(cumulative),
+        }
+    }
+
+    #[test]
+    fn display_tree() {
+        let tree = report("outer", 10, vec![
+            report("a", 4, vec![report("leaf", 1, vec![])]),
+            report("b", 1, vec![]),
+        ]);
+        let expected = "\
+outer: 10.0ms
+  a: 4.0ms (40.0%)
+    leaf: 1.0ms (25.0%)
+    (self): 3.0ms (75.0%)
+  b: 1.0ms (10.0%)
+  (self): 5.0ms (50.0%)";
+        assert_eq!(tree.to_string(), expected);
+        assert_eq!(report("flat", 2, vec![]).to_string(), "flat: 2.0ms");
+    }
+
+    #[test]
+    fn display_laps() {
+        let mut load = report("load", 12, vec![]);
+        load.laps = vec![lap("read", 4, 4), lap("parse", 6, 10)];
+        let expected = "\
+load: 12.0ms
+  - read: 4.0ms (at 4.0ms)
+  - parse: 6.0ms (at 10.0ms)";
+        assert_eq!(load.to_string(), expected);
+    }
+
+    #[test]
+    fn display_fields() {
+        let mut parse = report("parse", 2, vec![]);
+        parse.fields = vec![("file".into(), "foo.rs".into()), ("bytes".into(), 12040.into())];
+        assert_eq!(parse.to_string(), "parse{file=foo.rs, bytes=12040}: 2.0ms");
+    }
+
+    #[test]
+    fn display_paused() {
+        let mut io = report("io", 3, vec![]);
+        io.paused = Duration::from_millis(5);
+        io.pauses = 2;
+        assert_eq!(io.to_string(), "io: 3.0ms [paused 5.0ms, 2 pauses]");
+    }
+
+    #[test]
+    fn display_cpu_time() {
+        let mut parse = report("parse", 12, vec![report("lex", 6, vec![])]);
+        parse.cpu_time = Some(Duration::from_micros(4100));
+        parse.children[0].cpu_time = Some(Duration::from_millis(6));
+        let expected = "\
+parse: 12.0ms wall / 4.1ms cpu
+  lex: 6.0ms wall / 6.0ms cpu (50.0%)
+  (self): 6.0ms (50.0%)";
+        assert_eq!(parse.to_string(), expected);
+    }
+
+    #[test]
+    fn display_polls() {
+        let mut fetch = report("fetch", 120, vec![]);
+        fetch.polls = Some(Polls { count: 14, busy: Duration::from_micros(3200) });
+        assert_eq!(fetch.to_string(), "fetch: 120.0ms [busy 3.2ms, 14 polls]");
+    }
+
+    #[test]
+    fn display_slow() {
+        let mut parse = report("parse", 120, vec![]);
+        parse.warn_after = Some(Duration::from_millis(100));
+        assert!(parse.is_slow());
+        assert_eq!(parse.to_string(), "parse: 120.0ms [SLOW >= 100.0ms]");
+        assert_eq!(Colored(&parse).to_string(),
+                   "\x1b[1;33mparse: 120.0ms [SLOW >= 100.0ms]\x1b[0m");
+        parse.warn_after = Some(Duration::from_millis(200));
+        assert_eq!(parse.to_string(), "parse: 120.0ms");
+    }
+
+    #[test]
+    fn merged_children() {
+        let mut main = report("main", 2000, vec![]);
+        for _ in 0..MAX_CHILDREN {
+            push_child(&mut main.children, report("parse", 1, vec![]));
+        }
+        push_child(&mut main.children, report("parse", 1, vec![report("lex", 1, vec![])]));
+        push_child(&mut main.children, report("render", 2, vec![]));
+        push_child(&mut main.children, report("paint", 3, vec![]));
+        assert_eq!(main.children.len(), MAX_CHILDREN + 1);
+        let parse = &main.children[MAX_CHILDREN - 1];
+        assert_eq!((parse.count, parse.elapsed), (2, Duration::from_millis(2)));
+        assert!(parse.children.is_empty());
+        assert_eq!(main.children[MAX_CHILDREN].to_string(), "(other): 5.0ms [2 timers]");
+        assert_eq!(main.self_time(), Duration::from_millis(2000 - 1006));
+    }
+}
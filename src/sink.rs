@@ -0,0 +1,318 @@
+//! Destinations for timer reports.
+
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock};
+
+#[cfg(feature = "log")]
+use log::{log, Level};
+
+use report::{Colored, TimerReport};
+#[cfg(feature = "log")]
+use CowStr;
+
+static DEFAULT_SINK: RwLock<Option<Arc<dyn TimerSink>>> = RwLock::new(None);
+
+/// Something that receives the reports of completed timers.
+pub trait TimerSink: Send + Sync {
+    fn record(&self, report: &TimerReport);
+
+    /// Returns `true` if this sink aggregates the timings of individual
+    /// timers, rather than displaying or storing whole reports. The default
+    /// is `false`.
+    ///
+    /// Such a sink is passed the report of each nested timer as soon as it
+    /// finishes, if the outermost timer running on the same thread reports
+    /// to it. The reports are then not kept for the outermost timer's
+    /// report, so none of them have any `children`, and none are merged
+    /// (see `TimerReport`).
+    fn aggregates(&self) -> bool {
+        false
+    }
+}
+
+/// Prints each report to stderr. This is the default sink.
+///
+/// If stderr is a terminal, slow timers are highlighted.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StderrSink;
+
+/// Writes each report as a line to the wrapped writer.
+pub struct WriterSink<W> {
+    inner: Mutex<W>,
+}
+
+/// Stores reports in memory, so that they can be inspected later.
+#[derive(Debug, Default)]
+pub struct Collector {
+    reports: Mutex<Vec<TimerReport>>,
+}
+
+/// Logs each report through the `log` facade, so that it is filtered and
+/// formatted by the application's logger. Requires the `log` feature.
+///
+/// By default, reports are logged with the target `test_helpers` at the
+/// `Info` level; reports containing a slow timer are logged at `Warn`, if
+/// that is more severe.
+#[cfg(feature = "log")]
+#[derive(Debug, Clone)]
+pub struct LogSink {
+    target: CowStr,
+    level: Level,
+}
+
+/// Passes each report to a closure. Created with `from_fn`.
+pub struct FnSink<F>(F);
+
+/// Creates a sink that calls `f` with each report.
+pub fn from_fn<F>(f: F) -> FnSink<F>
+    where F: Fn(&TimerReport) + Send + Sync
+{
+    FnSink(f)
+}
+
+/// Sets the sink used by timers that were not given one explicitly.
+pub fn set_default_sink<S: TimerSink + 'static>(sink: S) {
+    *DEFAULT_SINK.write().unwrap() = Some(Arc::new(sink));
+}
+
+/// Restores the default sink to `StderrSink`.
+pub fn reset_default_sink() {
+    *DEFAULT_SINK.write().unwrap() = None;
+}
+
+pub(crate) fn default_sink() -> Option<Arc<dyn TimerSink>> {
+    DEFAULT_SINK.read().unwrap().clone()
+}
+
+/// Records `report` to the default sink.
+pub(crate) fn record_default(report: &TimerReport) {
+    match default_sink() {
+        Some(sink) => sink.record(report),
+        None => StderrSink.record(report),
+    }
+}
+
+/// Returns `true` if `sink`, or the default sink if it is `None`,
+/// aggregates timings.
+pub(crate) fn aggregates(sink: Option<&Arc<dyn TimerSink>>) -> bool {
+    match sink {
+        Some(sink) => sink.aggregates(),
+        None => default_sink().map(|sink| sink.aggregates()).unwrap_or(false),
+    }
+}
+
+/// Records `report` to `sink`, or to the default sink if it is `None`.
+pub(crate) fn record_to(sink: Option<&Arc<dyn TimerSink>>, report: &TimerReport) {
+    match sink {
+        Some(sink) => sink.record(report),
+        None => record_default(report),
+    }
+}
+
+impl TimerSink for StderrSink {
+    fn record(&self, report: &TimerReport) {
+        if report.any_slow() && io::stderr().is_terminal() {
+            eprintln!("{}", Colored(report));
+        } else {
+            eprintln!("{}", report);
+        }
+    }
+}
+
+impl<W: Write + Send> WriterSink<W> {
+    pub fn new(writer: W) -> Self {
+        WriterSink { inner: Mutex::new(writer) }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> TimerSink for WriterSink<W> {
+    fn record(&self, report: &TimerReport) {
+        let mut writer = self.inner.lock().unwrap();
+        // a failed write shouldn't take down the code being timed
+        let _ = writeln!(writer, "{}", report);
+    }
+}
+
+impl<W> fmt::Debug for WriterSink<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WriterSink").finish()
+    }
+}
+
+#[cfg(feature = "log")]
+impl LogSink {
+    pub fn new() -> Self {
+        LogSink {
+            target: CowStr::Borrowed("test_helpers"),
+            level: Level::Info,
+        }
+    }
+
+    /// Sets the target reports are logged with.
+    pub fn target<S: Into<CowStr>>(mut self, target: S) -> Self {
+        self.target = target.into();
+        self
+    }
+
+    /// Sets the level reports are logged at.
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+}
+
+#[cfg(feature = "log")]
+impl Default for LogSink {
+    fn default() -> Self {
+        LogSink::new()
+    }
+}
+
+#[cfg(feature = "log")]
+impl TimerSink for LogSink {
+    fn record(&self, report: &TimerReport) {
+        let level = if report.any_slow() { self.level.min(Level::Warn) } else { self.level };
+        log!(target: &self.target, level, "{}", report);
+    }
+}
+
+impl Collector {
+    pub fn new() -> Self {
+        Collector::default()
+    }
+
+    /// Returns a copy of all the reports collected so far.
+    pub fn reports(&self) -> Vec<TimerReport> {
+        self.reports.lock().unwrap().clone()
+    }
+
+    /// Removes and returns all the reports collected so far.
+    pub fn take(&self) -> Vec<TimerReport> {
+        mem::take(&mut *self.reports.lock().unwrap())
+    }
+}
+
+impl TimerSink for Collector {
+    fn record(&self, report: &TimerReport) {
+        self.reports.lock().unwrap().push(report.clone());
+    }
+}
+
+impl<F> TimerSink for FnSink<F>
+    where F: Fn(&TimerReport) + Send + Sync
+{
+    fn record(&self, report: &TimerReport) {
+        (self.0)(report)
+    }
+}
+
+impl<F> fmt::Debug for FnSink<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FnSink").finish()
+    }
+}
+
+impl<T: TimerSink + ?Sized> TimerSink for &T {
+    fn record(&self, report: &TimerReport) {
+        (**self).record(report)
+    }
+
+    fn aggregates(&self) -> bool {
+        (**self).aggregates()
+    }
+}
+
+impl<T: TimerSink + ?Sized> TimerSink for Arc<T> {
+    fn record(&self, report: &TimerReport) {
+        (**self).record(report)
+    }
+
+    fn aggregates(&self) -> bool {
+        (**self).aggregates()
+    }
+}
+
+#[cfg(all(test, not(timing_off)))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use BlockTimer;
+
+    #[test]
+    fn collector() {
+        let collector = Arc::new(Collector::new());
+        BlockTimer::with_sink("one", collector.clone());
+        BlockTimer::with_sink("two", collector.clone());
+        let labels = collector.take().into_iter()
+            .map(|r| r.label)
+            .collect::<Vec<_>>();
+        assert_eq!(labels, vec!["one", "two"]);
+        assert!(collector.reports().is_empty());
+    }
+
+    #[test]
+    fn writer() {
+        let sink = Arc::new(WriterSink::new(Vec::new()));
+        BlockTimer::with_sink("write", sink.clone());
+        let sink = Arc::try_unwrap(sink).unwrap();
+        let text = String::from_utf8(sink.into_inner()).unwrap();
+        assert!(text.starts_with("write: "));
+        assert!(text.ends_with('\n'));
+    }
+
+    #[test]
+    fn closure() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let count2 = count.clone();
+        let sink = from_fn(move |_: &TimerReport| {
+            count2.fetch_add(1, Ordering::SeqCst);
+        });
+        let mut timer = BlockTimer::with_sink("fn", sink);
+        timer.stop();
+        drop(timer);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn log() {
+        use std::time::Duration;
+        use log::{LevelFilter, Log, Metadata, Record};
+
+        static RECORDS: Mutex<Vec<(String, Level, String)>> = Mutex::new(Vec::new());
+
+        struct TestLogger;
+
+        impl Log for TestLogger {
+            fn enabled(&self, _: &Metadata) -> bool { true }
+
+            fn log(&self, record: &Record) {
+                RECORDS.lock().unwrap().push((record.target().to_owned(), record.level(),
+                                              record.args().to_string()));
+            }
+
+            fn flush(&self) {}
+        }
+
+        log::set_logger(&TestLogger).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+        let sink = Arc::new(LogSink::new().target("timings").level(Level::Debug));
+        BlockTimer::with_sink("quick", sink.clone());
+        BlockTimer::builder("slow")
+            .sink(sink)
+            .warn_after(Duration::default())
+            .start();
+
+        let records = RECORDS.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].0.as_str(), records[0].1), ("timings", Level::Debug));
+        assert!(records[0].2.starts_with("quick: "));
+        assert_eq!(records[1].1, Level::Warn);
+    }
+}
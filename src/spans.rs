@@ -0,0 +1,296 @@
+//! Integration with `tracing`, enabled by the `tracing` feature.
+//!
+//! Each `BlockTimer` opens an `INFO` span named `block_timer`, with its
+//! label as a field, inside whichever span is current when it starts. When
+//! it stops, it emits an event in that span with its `elapsed` time
+//! (formatted as a `PrettyDuration`) and `elapsed_ns`, then closes it.
+//!
+//! The span is never entered, as a timer may be stopped on a different
+//! thread from the one that started it, and a span can only be exited on
+//! the thread that entered it. So events and spans inside the timed block
+//! are not nested under the timer's span; enter a span of your own around
+//! the block for that.
+//!
+//! In the other direction, a `TimingLayer` reports `tracing` spans as if
+//! they were timers: when a span closes, a `TimerReport` covering its
+//! lifetime is passed to a sink, nested under the report for its closest
+//! enclosing span. The span's fields become the report's fields.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Metadata, Span, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use clock::{self, Clock};
+use report::{push_child, SourceLocation, TimerReport};
+use sink::{self, TimerSink};
+use value::Value;
+use {epoch, nanos_from_duration, threshold, toggle, CowStr, PrettyDuration};
+
+const TIMER_SPAN: &str = "block_timer";
+
+/// The span a `BlockTimer` holds open while it runs. It is closed when the
+/// timer is dropped.
+#[cfg_attr(timing_off, allow(dead_code))]
+pub(crate) struct TimerSpan(Span);
+
+#[cfg_attr(timing_off, allow(dead_code))]
+impl TimerSpan {
+    pub(crate) fn new(label: &str) -> TimerSpan {
+        TimerSpan(tracing::info_span!(parent: Span::current(), TIMER_SPAN, label))
+    }
+
+    /// Emits the timer's elapsed time in the span.
+    pub(crate) fn finish(&self, elapsed: Duration) {
+        tracing::info!(parent: &self.0, elapsed = %PrettyDuration::new(elapsed),
+                       elapsed_ns = nanos_from_duration(elapsed));
+    }
+}
+
+fn is_timer_span(metadata: &Metadata) -> bool {
+    metadata.name() == TIMER_SPAN && metadata.target() == module_path!()
+}
+
+/// A `tracing_subscriber` layer that reports spans to a `TimerSink`.
+///
+/// Spans opened by a `BlockTimer` are skipped, as the timer reports itself.
+/// Spans excluded by the timer filter (see `filter`) are skipped too, and
+/// the default minimum duration and slow threshold apply.
+///
+/// ```
+/// # extern crate tracing;
+/// # extern crate tracing_subscriber;
+/// # extern crate test_helpers;
+/// use tracing_subscriber::layer::SubscriberExt;
+/// use test_helpers::registry;
+/// use test_helpers::spans::TimingLayer;
+///
+/// # fn main() {
+/// let subscriber = tracing_subscriber::registry()
+///     .with(TimingLayer::new().with_sink(registry::global()));
+/// tracing::subscriber::with_default(subscriber, || {
+///     let _span = tracing::info_span!("request").entered();
+///     // ...
+/// });
+/// # if test_helpers::toggle::STATIC_ENABLED {
+/// assert!(registry::global().get("request").is_some());
+/// # }
+/// # }
+/// ```
+pub struct TimingLayer {
+    sink: Option<Arc<dyn TimerSink>>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+/// Stored in the extensions of each span being timed.
+struct SpanTiming {
+    start: Instant,
+    fields: Vec<(CowStr, Value)>,
+    children: Vec<TimerReport>,
+}
+
+/// Collects the fields recorded on a span.
+struct FieldVisitor<'a>(&'a mut Vec<(CowStr, Value)>);
+
+impl TimingLayer {
+    /// Creates a layer that reports to the default sink.
+    pub fn new() -> Self {
+        TimingLayer {
+            sink: None,
+            clock: clock::default_clock(),
+        }
+    }
+
+    /// Reports to `sink`, instead of to the default sink.
+    pub fn with_sink<T: TimerSink + 'static>(mut self, sink: T) -> Self {
+        self.sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Reads the time from `clock`, instead of from the default clock.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+}
+
+impl Default for TimingLayer {
+    fn default() -> Self {
+        TimingLayer::new()
+    }
+}
+
+impl<S> Layer<S> for TimingLayer
+    where S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes, id: &Id, ctx: Context<S>) {
+        let metadata = attrs.metadata();
+        if is_timer_span(metadata)
+            || !toggle::label_enabled(metadata.name(), metadata.module_path()) {
+            return;
+        }
+        epoch();
+        if let Some(span) = ctx.span(id) {
+            let mut fields = Vec::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            span.extensions_mut().insert(SpanTiming {
+                start: clock::now(self.clock.as_ref()),
+                fields,
+                children: Vec::new(),
+            });
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record, ctx: Context<S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<SpanTiming>() {
+                values.record(&mut FieldVisitor(&mut timing.fields));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let timing = match span.extensions_mut().remove::<SpanTiming>() {
+            Some(timing) => timing,
+            None => return,
+        };
+        let elapsed = clock::now(self.clock.as_ref()) - timing.start;
+        if threshold::below_min(elapsed, None) {
+            return;
+        }
+        let metadata = span.metadata();
+        let mut report = TimerReport::new(Cow::Borrowed(metadata.name()), timing.start, elapsed);
+        report.fields = timing.fields;
+        report.location = match (metadata.module_path(), metadata.file(), metadata.line()) {
+            (Some(module_path), Some(file), Some(line)) => {
+                Some(SourceLocation { module_path, file, line })
+            }
+            _ => None,
+        };
+        report.children = timing.children;
+
+        // a span's parents stay open at least as long as it does
+        let parent = span.scope().skip(1)
+            .find(|parent| parent.extensions().get::<SpanTiming>().is_some());
+        match parent {
+            Some(ref parent) if !sink::aggregates(self.sink.as_ref()) => {
+                if let Some(timing) = parent.extensions_mut().get_mut::<SpanTiming>() {
+                    push_child(&mut timing.children, report);
+                }
+            }
+            _ => sink::record_to(self.sink.as_ref(), &report),
+        }
+    }
+}
+
+impl<'a> FieldVisitor<'a> {
+    fn push<V: Into<Value>>(&mut self, field: &Field, value: V) {
+        self.0.push((Cow::Borrowed(field.name()), value.into()));
+    }
+}
+
+impl<'a> Visit for FieldVisitor<'a> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, value);
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.push(field, value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, value.to_owned());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(field, format!("{:?}", value));
+    }
+}
+
+#[cfg(all(test, not(timing_off)))]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::thread;
+    use tracing::{Dispatch, Event};
+    use tracing_subscriber::layer::SubscriberExt;
+    use sink::Collector;
+    use BlockTimer;
+
+    /// Records the names of the fields of each event.
+    #[derive(Clone, Default)]
+    struct EventFields(Arc<Mutex<Vec<&'static str>>>);
+
+    impl Visit for EventFields {
+        fn record_debug(&mut self, field: &Field, _: &dyn fmt::Debug) {
+            self.0.lock().unwrap().push(field.name());
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for EventFields {
+        fn on_event(&self, event: &Event, _: Context<S>) {
+            event.record(&mut self.clone());
+        }
+    }
+
+    #[test]
+    fn spans_are_reported() {
+        let collector = Arc::new(Collector::new());
+        let events = EventFields::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(TimingLayer::new().with_sink(collector.clone()))
+            .with(events.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let _outer = tracing::info_span!("outer").entered();
+            {
+                let _timer = BlockTimer::with_sink("timer", Collector::new());
+                let _inner = tracing::info_span!("inner", bytes = 12040u64).entered();
+            }
+            let _sibling = tracing::info_span!("sibling").entered();
+        });
+
+        let reports = collector.take();
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.label, "outer");
+        assert_eq!(report.location.as_ref().unwrap().file, file!());
+        let children = report.children.iter().map(|r| r.label.as_ref()).collect::<Vec<_>>();
+        assert_eq!(children, vec!["inner", "sibling"]);
+        assert_eq!(report.children[0].fields, vec![("bytes".into(), Value::UInt(12040))]);
+        assert_eq!(*events.0.lock().unwrap(), vec!["elapsed", "elapsed_ns"]);
+    }
+
+    #[test]
+    fn timer_moved_across_threads() {
+        let events = EventFields::default();
+        let dispatch = Dispatch::new(tracing_subscriber::registry().with(events.clone()));
+        tracing::dispatcher::with_default(&dispatch, || {
+            let timer = BlockTimer::with_sink("moved", Collector::new());
+            assert!(Span::current().is_none());
+            let dispatch = dispatch.clone();
+            thread::spawn(move || {
+                tracing::dispatcher::with_default(&dispatch, || drop(timer));
+            }).join().unwrap();
+            assert!(Span::current().is_none());
+        });
+        assert_eq!(*events.0.lock().unwrap(), vec!["elapsed", "elapsed_ns"]);
+    }
+}
@@ -0,0 +1,189 @@
+//! Tracks the timers running on each thread, so that nested timers can be
+//! reported as part of the timer that encloses them.
+
+use std::cell::RefCell;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+
+use report::{push_child, ThreadInfo, TimerReport};
+use sink::{self, TimerSink};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static STACK: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+    static ORPHANS: Arc<Orphans> = Arc::new(Orphans::default());
+    static THREAD: ThreadInfo = ThreadInfo {
+        id: NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed),
+        name: thread::current().name().map(Into::into),
+    };
+}
+
+struct Frame {
+    id: usize,
+    /// The timer's own sink, if it has one.
+    sink: Option<Arc<dyn TimerSink>>,
+    /// Whether the timer's sink aggregates timings. Only set for a timer
+    /// that isn't nested inside another.
+    aggregates: bool,
+    children: Vec<TimerReport>,
+}
+
+/// A timer registered with the thread that started it.
+pub(crate) struct Entry {
+    pub(crate) id: usize,
+    /// The orphans of the thread that started the timer, in case it is
+    /// finished on another thread.
+    orphans: Option<Arc<Orphans>>,
+}
+
+/// Timers that were finished on a thread other than the one that started
+/// them; their frames are removed the next time the starting thread looks.
+///
+/// Each thread has its own, shared with the timers it starts, so that the
+/// orphans of a thread that has exited are freed along with its timers.
+#[derive(Default)]
+struct Orphans {
+    ids: Mutex<Vec<usize>>,
+    any: AtomicBool,
+}
+
+/// Returns the id and name of the current thread.
+pub(crate) fn current_thread() -> ThreadInfo {
+    THREAD.try_with(ThreadInfo::clone).unwrap_or(ThreadInfo { id: 0, name: None })
+}
+
+/// Returns the id of the current thread.
+#[cfg_attr(timing_off, allow(dead_code))]
+pub(crate) fn current_thread_id() -> u64 {
+    THREAD.try_with(|thread| thread.id).unwrap_or(0)
+}
+
+/// Registers a newly started timer, that reports to `sink`, with the
+/// current thread, returning the entry used to finish it.
+pub(crate) fn push(sink: Option<Arc<dyn TimerSink>>) -> Entry {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut orphaned = Vec::new();
+    let _ = STACK.try_with(|stack| {
+        let mut stack = stack.borrow_mut();
+        orphaned = remove_orphans(&mut stack);
+        let aggregates = stack.is_empty() && sink::aggregates(sink.as_ref());
+        stack.push(Frame { id, sink, aggregates, children: Vec::new() });
+    });
+    record_orphaned(orphaned);
+    Entry { id, orphans: ORPHANS.try_with(Arc::clone).ok() }
+}
+
+/// Removes the timer `entry` from the current thread, returning the reports
+/// of any timers that finished while nested inside it, and the id of the
+/// timer that it is itself nested inside, if any.
+pub(crate) fn pop(entry: &Entry) -> (Vec<TimerReport>, Option<usize>) {
+    let mut orphaned = Vec::new();
+    let popped = STACK.try_with(|stack| {
+        let mut stack = stack.borrow_mut();
+        orphaned = remove_orphans(&mut stack);
+        let idx = stack.iter().rposition(|frame| frame.id == entry.id)?;
+        let frame = stack.remove(idx);
+        let parent = idx.checked_sub(1).map(|i| stack[i].id);
+        Some((frame.children, parent))
+    });
+    record_orphaned(orphaned);
+    match popped {
+        Ok(Some(popped)) => popped,
+        _ => {
+            if let Some(ref orphans) = entry.orphans {
+                orphans.ids.lock().unwrap().push(entry.id);
+                orphans.any.store(true, Ordering::Release);
+            }
+            (Vec::new(), None)
+        }
+    }
+}
+
+/// Adds `report` to the children of the running timer `parent`, or if the
+/// outermost timer's sink aggregates timings, records it there. If `parent`
+/// isn't running on this thread, the report is handed back.
+pub(crate) fn attach(parent: usize, report: TimerReport) -> Option<TimerReport> {
+    let mut report = Some(report);
+    let mut aggregate = None;
+    let _ = STACK.try_with(|stack| {
+        let mut stack = stack.borrow_mut();
+        let outermost = stack.first().filter(|f| f.aggregates).map(|f| f.sink.clone());
+        if let Some(frame) = stack.iter_mut().rev().find(|f| f.id == parent) {
+            match outermost {
+                Some(sink) => aggregate = Some(sink),
+                None => push_child(&mut frame.children, report.take().unwrap()),
+            }
+        }
+    });
+    // recorded once the stack is released, in case the sink starts a timer
+    if let Some(sink) = aggregate {
+        sink::record_to(sink.as_ref(), &report.take().unwrap());
+    }
+    report
+}
+
+/// Removes the frames of timers that were finished on another thread. The
+/// frames of those that weren't nested inside another timer are returned,
+/// so that the reports nested inside them can be recorded.
+fn remove_orphans(stack: &mut Vec<Frame>) -> Vec<Frame> {
+    let orphans = ORPHANS.try_with(|orphans| {
+        if orphans.any.load(Ordering::Relaxed) && orphans.any.swap(false, Ordering::Acquire) {
+            mem::take(&mut *orphans.ids.lock().unwrap())
+        } else {
+            Vec::new()
+        }
+    });
+    let orphans = match orphans {
+        Ok(ref orphans) if !orphans.is_empty() => orphans,
+        _ => return Vec::new(),
+    };
+    let mut outermost = Vec::new();
+    let mut i = 0;
+    while i < stack.len() {
+        if orphans.contains(&stack[i].id) {
+            // anything that finished inside the orphan moves up a level
+            let frame = stack.remove(i);
+            if i > 0 {
+                for child in frame.children {
+                    push_child(&mut stack[i - 1].children, child);
+                }
+            } else {
+                outermost.push(frame);
+            }
+        } else {
+            i += 1;
+        }
+    }
+    outermost
+}
+
+/// Records the reports nested inside orphaned frames to the sinks of the
+/// timers that owned them, as there is no timer left to include them.
+fn record_orphaned(frames: Vec<Frame>) {
+    for frame in frames {
+        for report in &frame.children {
+            sink::record_to(frame.sink.as_ref(), report);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orphan_of_exited_thread() {
+        let entry = thread::spawn(|| push(None)).join().unwrap();
+        let (children, parent) = pop(&entry);
+        assert!(children.is_empty() && parent.is_none());
+        let orphans = entry.orphans.as_ref().unwrap();
+        assert_eq!(*orphans.ids.lock().unwrap(), vec![entry.id]);
+        // only the entry refers to the exited thread's orphans
+        assert_eq!(Arc::strong_count(orphans), 1);
+        assert!(!ORPHANS.with(|orphans| orphans.any.load(Ordering::Relaxed)));
+    }
+}
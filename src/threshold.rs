@@ -0,0 +1,48 @@
+//! Process-wide defaults for the thresholds that can be set on a
+//! `TimerBuilder`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const UNSET: u64 = u64::MAX;
+
+static MIN_DURATION: AtomicU64 = AtomicU64::new(UNSET);
+static WARN_AFTER: AtomicU64 = AtomicU64::new(UNSET);
+
+/// Sets the minimum duration for timers that don't set one themselves;
+/// timers that finish faster than this are not reported.
+pub fn set_default_min_duration(min: Option<Duration>) {
+    store(&MIN_DURATION, min);
+}
+
+/// Sets the duration after which timers that don't set a limit themselves
+/// are reported as slow.
+pub fn set_default_warn_after(limit: Option<Duration>) {
+    store(&WARN_AFTER, limit);
+}
+
+pub(crate) fn default_min_duration() -> Option<Duration> {
+    load(&MIN_DURATION)
+}
+
+pub(crate) fn default_warn_after() -> Option<Duration> {
+    load(&WARN_AFTER)
+}
+
+/// Returns `true` if a timer that ran for `elapsed` finished too quickly to
+/// be reported, given its own minimum duration, if it has one.
+pub(crate) fn below_min(elapsed: Duration, min: Option<Duration>) -> bool {
+    min.or_else(default_min_duration).map(|min| elapsed < min).unwrap_or(false)
+}
+
+fn store(slot: &AtomicU64, value: Option<Duration>) {
+    let nanos = value.map(|d| d.as_nanos().min(UNSET as u128 - 1) as u64);
+    slot.store(nanos.unwrap_or(UNSET), Ordering::Relaxed);
+}
+
+fn load(slot: &AtomicU64) -> Option<Duration> {
+    match slot.load(Ordering::Relaxed) {
+        UNSET => None,
+        nanos => Some(Duration::from_nanos(nanos)),
+    }
+}
@@ -0,0 +1,133 @@
+//! Switches for turning timers off.
+//!
+//! Timers can be compiled out entirely with the `timing-off` cargo feature,
+//! or only in release builds with `release-timing-off`; `BlockTimer` is then
+//! an empty type whose methods do nothing. Otherwise, the
+//! `TEST_HELPERS_TIMING` environment variable is read the first time a
+//! timer starts:
+//!
+//! - `on` (the default) enables every timer;
+//! - `off` disables every timer;
+//! - `filter` enables only the timers matched by `TEST_HELPERS_TIMERS`; see
+//!   the `filter` module.
+//!
+//! If `TEST_HELPERS_TIMING` is unset but `TEST_HELPERS_TIMERS` is set, the
+//! mode is `filter`. A disabled timer does no work when it starts or stops,
+//! and reports nothing.
+
+use std::env;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use filter::{self, FILTER_ENV};
+
+/// `false` if timers were compiled out by a cargo feature.
+pub const STATIC_ENABLED: bool = !cfg!(timing_off);
+
+const MODE_ENV: &str = "TEST_HELPERS_TIMING";
+
+const UNINIT: u8 = 0;
+static MODE: AtomicU8 = AtomicU8::new(UNINIT);
+
+/// Whether timers are enabled at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimingMode {
+    /// No timers are enabled.
+    Off,
+    /// Every timer is enabled.
+    On,
+    /// Only timers matched by `filter::current()` are enabled.
+    Filter,
+}
+
+impl TimingMode {
+    fn parse(s: &str) -> Option<TimingMode> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" | "0" | "false" => Some(TimingMode::Off),
+            "on" | "1" | "true" => Some(TimingMode::On),
+            "filter" => Some(TimingMode::Filter),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            TimingMode::Off => 1,
+            TimingMode::On => 2,
+            TimingMode::Filter => 3,
+        }
+    }
+
+    fn from_u8(n: u8) -> TimingMode {
+        match n {
+            1 => TimingMode::Off,
+            3 => TimingMode::Filter,
+            _ => TimingMode::On,
+        }
+    }
+
+    fn from_env() -> TimingMode {
+        match env::var(MODE_ENV) {
+            Ok(value) => TimingMode::parse(&value).unwrap_or_else(|| {
+                eprintln!("{}: unknown mode {:?}, expected off, on or filter",
+                          MODE_ENV, value);
+                TimingMode::On
+            }),
+            Err(_) if env::var_os(FILTER_ENV).is_some() => TimingMode::Filter,
+            Err(_) => TimingMode::On,
+        }
+    }
+}
+
+/// Returns the current mode, reading it from the environment on first use.
+pub fn timing_mode() -> TimingMode {
+    match MODE.load(Ordering::Relaxed) {
+        UNINIT => {
+            let mode = TimingMode::from_env();
+            // a mode set explicitly in the meantime takes precedence
+            let _ = MODE.compare_exchange(UNINIT, mode.to_u8(), Ordering::Relaxed,
+                                          Ordering::Relaxed);
+            TimingMode::from_u8(MODE.load(Ordering::Relaxed))
+        }
+        n => TimingMode::from_u8(n),
+    }
+}
+
+/// Overrides the mode read from the environment.
+pub fn set_timing_mode(mode: TimingMode) {
+    MODE.store(mode.to_u8(), Ordering::Relaxed);
+}
+
+/// Returns `true` unless timers are switched off, either at compile time or
+/// at runtime. Timers may still be disabled individually by the filter.
+#[inline]
+pub fn timing_enabled() -> bool {
+    STATIC_ENABLED && timing_mode() != TimingMode::Off
+}
+
+/// Returns `true` if a timer with this label, created in `module_path`,
+/// should run.
+pub(crate) fn label_enabled(label: &str, module_path: Option<&str>) -> bool {
+    if !timing_enabled() {
+        return false;
+    }
+    match timing_mode() {
+        TimingMode::Filter => filter::current().matches(label, module_path),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mode() {
+        assert_eq!(TimingMode::parse("off"), Some(TimingMode::Off));
+        assert_eq!(TimingMode::parse(" On\n"), Some(TimingMode::On));
+        assert_eq!(TimingMode::parse("filter"), Some(TimingMode::Filter));
+        assert_eq!(TimingMode::parse("sometimes"), None);
+        for &mode in &[TimingMode::Off, TimingMode::On, TimingMode::Filter] {
+            assert_eq!(TimingMode::from_u8(mode.to_u8()), mode);
+        }
+    }
+}
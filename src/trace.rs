@@ -0,0 +1,200 @@
+//! Export of timers in the Chrome Trace Event format, for viewing on a
+//! timeline in `chrome://tracing` or Perfetto.
+//!
+//! A `TraceRecorder` is a `TimerSink` that keeps every timer it receives,
+//! including nested timers; its contents can be written out as a trace file
+//! at any point. The global recorder can be installed as the default sink
+//! with `enable`.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as FmtWrite};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+use event::{self, TimingEvent};
+use exit;
+use json;
+use report::TimerReport;
+use sink::{self, TimerSink};
+
+static GLOBAL: TraceRecorder = TraceRecorder::new();
+static SAVE_AT_EXIT: exit::SaveAtExit = exit::SaveAtExit::new();
+
+/// Records timers, to be written out as a trace file.
+#[derive(Debug, Default)]
+pub struct TraceRecorder {
+    events: Mutex<Vec<TimingEvent>>,
+}
+
+/// Returns the global recorder.
+pub fn global() -> &'static TraceRecorder {
+    &GLOBAL
+}
+
+/// Makes the global recorder the default sink, so that timers without a
+/// sink of their own are recorded instead of printed.
+pub fn enable() {
+    sink::set_default_sink(global());
+}
+
+/// Arranges for the global recorder to be saved to `path` when the process
+/// exits normally. If this is called more than once, the last path is
+/// used.
+pub fn save_at_exit<P: Into<PathBuf>>(path: P) {
+    extern "C" fn save_global() {
+        SAVE_AT_EXIT.save("trace", |path| global().save(path));
+    }
+
+    SAVE_AT_EXIT.set_path(path.into(), save_global);
+}
+
+impl TraceRecorder {
+    pub const fn new() -> Self {
+        TraceRecorder { events: Mutex::new(Vec::new()) }
+    }
+
+    /// The number of timers recorded so far.
+    pub fn len(&self) -> usize {
+        self.events.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Discards all recorded timers.
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+
+    /// Writes the recorded timers as a trace file to `path`, creating any
+    /// missing parent directories.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        exit::write_file(path, |writer| self.write_to(writer))
+    }
+
+    /// Writes the recorded timers in the Trace Event format.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.to_json().as_bytes())
+    }
+
+    /// Returns the recorded timers in the Trace Event format.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out).unwrap();
+        out
+    }
+
+    fn write_json(&self, out: &mut String) -> fmt::Result {
+        let events = self.events.lock().unwrap();
+        let pid = process::id();
+        let threads = events.iter()
+            .map(|e| (e.thread_id, e.thread_name.as_ref()))
+            .collect::<BTreeMap<_, _>>();
+
+        out.push_str("{\"traceEvents\":[");
+        let mut first = true;
+        let mut separator = |out: &mut String| {
+            out.push_str(if first { "\n" } else { ",\n" });
+            first = false;
+        };
+        for (tid, name) in threads {
+            separator(out);
+            let name = match name {
+                Some(name) => name.clone(),
+                None => format!("thread {}", tid),
+            };
+            write!(out, "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":{},\"tid\":{},\
+                         \"args\":{{\"name\":", pid, tid)?;
+            json::write_str(out, &name)?;
+            out.push_str("}}");
+        }
+        for event in events.iter() {
+            separator(out);
+            out.push_str("{\"name\":");
+            json::write_str(out, &event.label)?;
+            write!(out, ",\"cat\":\"timer\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\
+                         \"pid\":{},\"tid\":{},\"args\":{{",
+                   Micros(event.start_ns), Micros(event.duration_ns), pid, event.thread_id)?;
+            for (i, (key, value)) in event.metadata.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                json::write_str(out, key)?;
+                out.push(':');
+                json::write_value(out, value)?;
+            }
+            if !event.laps.is_empty() {
+                if !event.metadata.is_empty() {
+                    out.push(',');
+                }
+                out.push_str("\"laps\":");
+                event::write_laps(out, &event.laps)?;
+            }
+            out.push_str("}}");
+        }
+        out.push_str("\n],\"displayTimeUnit\":\"ms\"}\n");
+        Ok(())
+    }
+}
+
+impl TimerSink for TraceRecorder {
+    fn record(&self, report: &TimerReport) {
+        self.events.lock().unwrap().extend(report.events());
+    }
+}
+
+/// Formats nanoseconds as fractional microseconds, the unit of trace
+/// timestamps.
+struct Micros(u64);
+
+impl fmt::Display for Micros {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:03}", self.0 / 1000, self.0 % 1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(not(timing_off))]
+    use std::sync::Arc;
+    #[cfg(not(timing_off))]
+    use std::thread;
+    #[cfg(not(timing_off))]
+    use BlockTimer;
+
+    #[cfg(not(timing_off))]
+    #[test]
+    fn trace_json() {
+        let recorder = Arc::new(TraceRecorder::new());
+        let sink = recorder.clone();
+        thread::Builder::new().name("worker".into()).spawn(move || {
+            let mut outer = BlockTimer::with_sink("outer", sink);
+            BlockTimer::new("inner \"quoted\"");
+            outer.lap("iter");
+            outer.lap("iter");
+        }).unwrap().join().unwrap();
+        assert_eq!(recorder.len(), 2);
+
+        let json = recorder.to_json();
+        let lines = json.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "{\"traceEvents\":[");
+        assert!(lines[1].contains("\"ph\":\"M\""));
+        assert!(lines[1].ends_with("\"args\":{\"name\":\"worker\"}},"));
+        assert!(lines[2].starts_with("{\"name\":\"outer\",\"cat\":\"timer\",\"ph\":\"X\""));
+        assert_eq!(lines[2].matches("{\"label\":\"iter\"").count(), 2);
+        assert!(lines[2].contains("\"laps\":[{\"label\":\"iter\""));
+        assert!(lines[3].starts_with("{\"name\":\"inner \\\"quoted\\\"\""));
+        assert_eq!(lines[4], "],\"displayTimeUnit\":\"ms\"}");
+    }
+
+    #[test]
+    fn micros() {
+        assert_eq!(Micros(1_234_567).to_string(), "1234.567");
+        assert_eq!(Micros(5).to_string(), "0.005");
+    }
+}
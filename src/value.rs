@@ -0,0 +1,79 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use super::CowStr;
+
+/// A value attached to a timing event's metadata.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Str(CowStr),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Bool(b) => b.fmt(f),
+            Value::Int(i) => i.fmt(f),
+            Value::UInt(u) => u.fmt(f),
+            Value::Float(x) => x.fmt(f),
+            Value::Str(ref s) => s.fmt(f),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+macro_rules! impl_from_int {
+    ($variant:ident, $target:ty, $($t:ty),*) => {
+        $(
+            impl From<$t> for Value {
+                fn from(n: $t) -> Value {
+                    Value::$variant(n as $target)
+                }
+            }
+        )*
+    }
+}
+
+impl_from_int!(Int, i64, i8, i16, i32, i64, isize);
+impl_from_int!(UInt, u64, u8, u16, u32, u64, usize);
+
+impl From<f32> for Value {
+    fn from(x: f32) -> Value {
+        Value::Float(x.into())
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Value {
+        Value::Float(x)
+    }
+}
+
+/// Copies the string. A `&'static str` can be passed as a `CowStr` instead,
+/// to avoid the copy.
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Value {
+        Value::Str(Cow::Owned(s.to_owned()))
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Str(s.into())
+    }
+}
+
+impl From<CowStr> for Value {
+    fn from(s: CowStr) -> Value {
+        Value::Str(s)
+    }
+}
@@ -0,0 +1,263 @@
+//! A background thread that reports timers which have been running for too
+//! long, such as a block that has deadlocked.
+//!
+//! A `BlockTimer` that never finishes is never reported, so a hung block
+//! produces no output at all. Once the watchdog is started, every timer
+//! registers itself while it runs, and the watchdog prints any that run
+//! past the configured limit.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use test_helpers::watchdog::Watchdog;
+//!
+//! Watchdog::new(Duration::from_secs(5)).capture_backtraces(true).start();
+//! ```
+
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use stack;
+use {CowStr, PrettyDuration};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Incremented whenever the watchdog thread should exit.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+static BACKTRACES: AtomicBool = AtomicBool::new(false);
+static ACTIVE: Mutex<BTreeMap<usize, Active>> = Mutex::new(BTreeMap::new());
+static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+
+type Callback = Arc<dyn Fn(&StuckTimer) + Send + Sync>;
+
+/// Configures and starts the watchdog.
+pub struct Watchdog {
+    limit: Duration,
+    interval: Duration,
+    backtraces: bool,
+    callback: Option<Callback>,
+}
+
+/// A timer that has been running for longer than the watchdog's limit.
+///
+/// The `Display` impl describes the timer, including the backtrace of where
+/// it was started if one was captured.
+#[derive(Debug, Clone)]
+pub struct StuckTimer {
+    pub label: CowStr,
+    pub elapsed: Duration,
+    pub thread_name: String,
+    pub backtrace: Option<Arc<Backtrace>>,
+}
+
+struct Active {
+    label: CowStr,
+    started: Instant,
+    thread_name: String,
+    backtrace: Option<Arc<Backtrace>>,
+    reported: bool,
+}
+
+#[derive(Clone)]
+struct Config {
+    limit: Duration,
+    interval: Duration,
+    callback: Option<Callback>,
+}
+
+impl Watchdog {
+    /// Reports timers that run for longer than `limit`.
+    pub fn new(limit: Duration) -> Self {
+        Watchdog {
+            limit,
+            interval: (limit / 10).max(Duration::from_millis(1)),
+            backtraces: false,
+            callback: None,
+        }
+    }
+
+    /// Sets how often the watchdog checks for stuck timers. The default is a
+    /// tenth of the limit.
+    pub fn check_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Captures a backtrace whenever a timer starts, so that stuck timers
+    /// can be traced to where they were created. This is expensive.
+    pub fn capture_backtraces(mut self, capture: bool) -> Self {
+        self.backtraces = capture;
+        self
+    }
+
+    /// Calls `f` with each stuck timer, instead of printing it to stderr.
+    pub fn on_stuck<F>(mut self, f: F) -> Self
+        where F: Fn(&StuckTimer) + Send + Sync + 'static
+    {
+        self.callback = Some(Arc::new(f));
+        self
+    }
+
+    /// Starts the watchdog thread. If the watchdog is already running, its
+    /// configuration is replaced.
+    pub fn start(self) {
+        let config = Config {
+            limit: self.limit,
+            interval: self.interval,
+            callback: self.callback,
+        };
+        let already_running = CONFIG.lock().unwrap().replace(config).is_some();
+        BACKTRACES.store(self.backtraces, Ordering::Relaxed);
+        ENABLED.store(true, Ordering::Release);
+        if !already_running {
+            let generation = GENERATION.load(Ordering::SeqCst);
+            thread::Builder::new()
+                .name("test-helpers watchdog".into())
+                .spawn(move || run(generation))
+                .expect("failed to spawn watchdog thread");
+        }
+    }
+}
+
+/// Stops the watchdog thread, if it is running. Timers that are running
+/// when this is called are forgotten.
+pub fn stop() {
+    ENABLED.store(false, Ordering::Release);
+    let mut config = CONFIG.lock().unwrap();
+    if config.take().is_some() {
+        GENERATION.fetch_add(1, Ordering::SeqCst);
+    }
+    drop(config);
+    ACTIVE.lock().unwrap().clear();
+}
+
+/// Registers a started timer, if the watchdog is running.
+#[cfg_attr(timing_off, allow(dead_code))]
+pub(crate) fn register(id: usize, label: &CowStr) {
+    if !ENABLED.load(Ordering::Acquire) {
+        return;
+    }
+    let thread = stack::current_thread();
+    let thread_name = match thread.name {
+        Some(ref name) => name.to_string(),
+        None => format!("thread {}", thread.id),
+    };
+    let backtrace = if BACKTRACES.load(Ordering::Relaxed) {
+        Some(Arc::new(Backtrace::force_capture()))
+    } else {
+        None
+    };
+    let active = Active {
+        label: label.clone(),
+        started: Instant::now(),
+        thread_name,
+        backtrace,
+        reported: false,
+    };
+    ACTIVE.lock().unwrap().insert(id, active);
+}
+
+/// Removes a finished timer.
+#[cfg_attr(timing_off, allow(dead_code))]
+pub(crate) fn unregister(id: usize) {
+    if ENABLED.load(Ordering::Acquire) {
+        ACTIVE.lock().unwrap().remove(&id);
+    }
+}
+
+fn run(generation: usize) {
+    loop {
+        let config = match CONFIG.lock().unwrap().clone() {
+            Some(config) => config,
+            None => return,
+        };
+        thread::sleep(config.interval);
+        if GENERATION.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        let stuck = {
+            let mut active = ACTIVE.lock().unwrap();
+            active.values_mut()
+                .filter(|a| !a.reported && a.started.elapsed() >= config.limit)
+                .map(|a| {
+                    a.reported = true;
+                    StuckTimer {
+                        label: a.label.clone(),
+                        elapsed: a.started.elapsed(),
+                        thread_name: a.thread_name.clone(),
+                        backtrace: a.backtrace.clone(),
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        for timer in &stuck {
+            match config.callback {
+                Some(ref callback) => callback(timer),
+                None => eprintln!("{}", timer),
+            }
+        }
+    }
+}
+
+impl fmt::Display for StuckTimer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "watchdog: '{}' on thread '{}' has been running for {}",
+               self.label, self.thread_name, PrettyDuration::new(self.elapsed))?;
+        if let Some(ref backtrace) = self.backtrace {
+            write!(f, "\nstarted at:\n{}", backtrace)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(timing_off)))]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use BlockTimer;
+
+    /// Stops the watchdog when dropped, even if the test fails.
+    struct StopOnDrop;
+
+    impl Drop for StopOnDrop {
+        fn drop(&mut self) {
+            stop();
+        }
+    }
+
+    #[test]
+    fn reports_stuck_timer() {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let _stop = StopOnDrop;
+        Watchdog::new(Duration::from_millis(10))
+            .check_interval(Duration::from_millis(2))
+            .on_stuck(move |timer| { let _ = tx.lock().unwrap().send(timer.clone()); })
+            .start();
+        let handle = thread::Builder::new().name("stuck".into()).spawn(|| {
+            let _timer = BlockTimer::new("watchdog::stuck");
+            thread::sleep(Duration::from_millis(100));
+        }).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut stuck = None;
+        while let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+            match rx.recv_timeout(wait) {
+                Ok(timer) if timer.label == "watchdog::stuck" => {
+                    stuck = Some(timer);
+                    break;
+                }
+                Ok(_) => continue,
+                Err(_) => break,
+            }
+        }
+        handle.join().unwrap();
+        let stuck = stuck.expect("stuck timer was not reported");
+        assert_eq!(stuck.thread_name, "stuck");
+        assert!(stuck.elapsed >= Duration::from_millis(10));
+        assert!(stuck.to_string().starts_with(
+            "watchdog: 'watchdog::stuck' on thread 'stuck' has been running for "));
+    }
+}
@@ -0,0 +1,100 @@
+//! Process-wide settings. These would interfere with each other if they
+//! ran in parallel, so they run one after another, in a single test.
+
+#[macro_use]
+extern crate test_helpers;
+
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use test_helpers::filter::{self, Filter};
+use test_helpers::sink::{self, Collector};
+use test_helpers::toggle::{self, TimingMode};
+use test_helpers::{set_default_min_duration, set_default_warn_after, set_timing_mode,
+                   BlockTimer};
+
+mod render {
+    pub mod layout {
+        pub fn lines() {
+            time_block!("lines");
+        }
+    }
+
+    pub fn paint() {
+        time_block!("paint");
+    }
+}
+
+fn labels(collector: &Collector) -> Vec<String> {
+    collector.take().iter().map(|report| report.label.to_string()).collect()
+}
+
+fn filters(collector: &Collector) {
+    // read when the first timer starts, switching to filter mode
+    env::set_var("TEST_HELPERS_TIMERS", "parse*,-parse noisy");
+    assert!(BlockTimer::new("parse header").is_enabled());
+    assert_eq!(toggle::timing_mode(), TimingMode::Filter);
+    assert!(BlockTimer::builder("parser").start().is_enabled());
+    assert!(!BlockTimer::new("parse noisy").is_enabled());
+    assert!(!BlockTimer::new("render").is_enabled());
+    assert_eq!(labels(collector), vec!["parse header", "parser"]);
+
+    // macro timers match their module path too
+    filter::set_filter(Filter::parse("settings::render::layout"));
+    render::layout::lines();
+    render::paint();
+    assert!(!BlockTimer::new("lines").is_enabled());
+    assert_eq!(labels(collector), vec!["lines"]);
+
+    set_timing_mode(TimingMode::On);
+    assert!(BlockTimer::new("render").is_enabled());
+    collector.take();
+}
+
+fn default_sink(collector: &Arc<Collector>) {
+    let own = Arc::new(Collector::new());
+    BlockTimer::new("default");
+    BlockTimer::with_sink("own", own.clone());
+    assert_eq!(labels(collector), vec!["default"]);
+    assert_eq!(labels(&own), vec!["own"]);
+
+    // back to stderr
+    sink::reset_default_sink();
+    BlockTimer::new("stderr");
+    assert!(labels(collector).is_empty());
+    sink::set_default_sink(collector.clone());
+}
+
+fn default_thresholds(collector: &Collector) {
+    set_default_min_duration(Some(Duration::from_secs(60)));
+    BlockTimer::new("quick");
+    BlockTimer::builder("own minimum").min_duration(Duration::default()).start();
+    assert_eq!(labels(collector), vec!["own minimum"]);
+    set_default_min_duration(None);
+    BlockTimer::new("quick");
+    assert_eq!(labels(collector), vec!["quick"]);
+
+    set_default_warn_after(Some(Duration::default()));
+    BlockTimer::new("slow");
+    BlockTimer::builder("own limit").warn_after(Duration::from_secs(60)).start();
+    let reports = collector.take();
+    assert_eq!(reports[0].warn_after, Some(Duration::default()));
+    assert!(reports[0].is_slow());
+    assert!(!reports[1].is_slow());
+    set_default_warn_after(None);
+    BlockTimer::new("fast");
+    assert_eq!(collector.take()[0].warn_after, None);
+}
+
+#[test]
+fn process_wide_settings() {
+    if !toggle::STATIC_ENABLED {
+        return;
+    }
+    let collector = Arc::new(Collector::new());
+    sink::set_default_sink(collector.clone());
+    filters(&collector);
+    default_sink(&collector);
+    default_thresholds(&collector);
+}
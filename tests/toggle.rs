@@ -0,0 +1,73 @@
+//! The runtime switch. The timing mode is process-wide, so this has a test
+//! binary of its own, with a single test.
+
+#[macro_use]
+extern crate test_helpers;
+
+use std::env;
+use std::future::{self, Future};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use test_helpers::sink::{self, Collector};
+use test_helpers::toggle::{self, TimingMode};
+use test_helpers::{set_timing_mode, timing_enabled, BlockTimer, TimedExt};
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+/// Runs a `BlockTimer`, a `time_block!` and a `Timed` future, returning
+/// whether the first was enabled and whether the second's label was
+/// formatted.
+fn run_timers() -> (bool, bool) {
+    let enabled = BlockTimer::new("new").is_enabled();
+    let mut formatted = false;
+    {
+        time_block!("block {}", { formatted = true; 1 });
+    }
+    assert_eq!(block_on(future::ready(3).timed("timed")), 3);
+    (enabled, formatted)
+}
+
+fn labels(collector: &Collector) -> Vec<String> {
+    collector.take().iter().map(|report| report.label.to_string()).collect()
+}
+
+#[test]
+fn runtime_switch() {
+    // read when the first timer starts
+    env::set_var("TEST_HELPERS_TIMING", "off");
+    let collector = Arc::new(Collector::new());
+    sink::set_default_sink(collector.clone());
+
+    assert_eq!(toggle::timing_mode(), TimingMode::Off);
+    assert!(!timing_enabled());
+    assert_eq!(run_timers(), (false, false));
+    assert!(labels(&collector).is_empty());
+
+    set_timing_mode(TimingMode::On);
+    if toggle::STATIC_ENABLED {
+        assert!(timing_enabled());
+        assert_eq!(run_timers(), (true, true));
+        assert_eq!(labels(&collector), vec!["new", "block 1", "timed"]);
+    }
+
+    set_timing_mode(TimingMode::Off);
+    assert!(!timing_enabled());
+    assert_eq!(run_timers(), (false, false));
+    assert!(labels(&collector).is_empty());
+}